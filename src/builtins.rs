@@ -0,0 +1,218 @@
+use crate::env::Env;
+use crate::error::CrotchetError;
+use crate::object::{NativeFn, Object};
+use rand::Rng;
+use std::cmp::Ordering;
+use std::io::{self, Write};
+
+type Builtin = fn(&[Object]) -> Result<Object, CrotchetError>;
+
+// binds every builtin function in `env`. they're ordinary values, so programs
+// can pass them around or shadow them with `let`
+pub fn register(env: &mut Env) {
+  let builtins: [(&str, Option<usize>, Builtin); 10] = [
+    ("print", None, print),
+    ("input", None, input),
+    ("rand", Some(2), rand),
+    ("round", Some(1), round),
+    ("list", None, list),
+    ("first", Some(1), first),
+    ("rest", Some(1), rest),
+    ("len", Some(1), len),
+    ("exit", None, exit),
+    ("not", Some(1), not),
+  ];
+
+  for (name, arity, func) in builtins {
+    env.set(name, Object::NativeFn(NativeFn::new(name, arity, func)));
+  }
+}
+
+// print: takes a variable list of args, printing each on a single line
+// after running, goes to new line and returns the number of things printed
+#[allow(clippy::unnecessary_wraps)]
+fn print(args: &[Object]) -> Result<Object, CrotchetError> {
+  for val in args {
+    match val {
+      Object::Void => {}
+      Object::Number(n) => print!("{n}"),
+      Object::Bool(b) => print!("{b}"),
+      Object::Symbol(s, _) => print!("{s}"),
+      Object::Lambda(lambda) => {
+        print!("fn[");
+        for param in lambda.param_list() {
+          print!("{param} ");
+        }
+        print!("]");
+        for expr in lambda.body.iter() {
+          print!(" {expr}");
+        }
+      }
+      _ => print!("{val}"),
+    }
+  }
+
+  println!();
+  #[allow(clippy::cast_precision_loss)]
+  Ok(Object::Number(args.len() as f64)) // TODO beware "as" conversion?
+}
+
+// of the form [input "prompt"] where the prompt is optional
+// prints "prompt" on a newline, accepts input from the user
+// tries to parse input as a float, will return err upon fail
+fn input(args: &[Object]) -> Result<Object, CrotchetError> {
+  if args.len() > 1 {
+    return Err(CrotchetError::arity(
+      "Invalid number of arguments for `input`",
+    ));
+  }
+
+  if let Some(prompt) = args.first() {
+    print!("{prompt}");
+  }
+  match io::stdout().flush() {
+    Ok(()) => {}
+    Err(error) => {
+      return Err(CrotchetError::io(format!(
+        "`input` failed to flush input: {error}"
+      )))
+    }
+  }
+
+  let mut input = String::new();
+  match io::stdin().read_line(&mut input) {
+    Ok(_) => {}
+    Err(error) => {
+      return Err(CrotchetError::io(format!(
+        "`input` failed to read: {error}"
+      )))
+    }
+  }
+
+  match input.trim().parse::<f64>() {
+    Ok(number) => Ok(Object::Number(number)),
+    Err(_) => Err(CrotchetError::runtime(format!(
+      "`input` failed to parse {input} as float"
+    ))),
+  }
+}
+
+// of the form [rand x y]
+// returns a random number on the interval [x, y)
+fn rand(args: &[Object]) -> Result<Object, CrotchetError> {
+  match (&args[0], &args[1]) {
+    (Object::Number(i), Object::Number(j)) => {
+      if !i.is_finite() || !j.is_finite() {
+        return Err(CrotchetError::runtime("`rand` needs finite bounds"));
+      }
+      // NaN bounds compare as `None`, so they fail here too
+      if i.partial_cmp(j) != Some(Ordering::Less) {
+        return Err(CrotchetError::runtime(
+          "`rand` needs a lower bound less than its upper bound",
+        ));
+      }
+      if !(j - i).is_finite() {
+        return Err(CrotchetError::runtime(
+          "`rand` bounds are too far apart to pick between",
+        ));
+      }
+      let mut rng = rand::thread_rng();
+      let random_value: f64 = rng.gen_range(*i..*j);
+      Ok(Object::Number(random_value))
+    }
+    (_, _) => Err(CrotchetError::type_error(
+      "Invalid argument types for `rand`",
+    )),
+  }
+}
+
+fn round(args: &[Object]) -> Result<Object, CrotchetError> {
+  match &args[0] {
+    Object::Number(n) => Ok(Object::Number(n.round())),
+    _ => Err(CrotchetError::type_error(
+      "First argument of `round` must be a number",
+    )),
+  }
+}
+
+// makes a list out of its args
+// [list 1 2 3] ; returns [1 2 3]
+#[allow(clippy::unnecessary_wraps)]
+fn list(args: &[Object]) -> Result<Object, CrotchetError> {
+  Ok(Object::ListData(args.to_vec()))
+}
+
+// returns the first element of a list:
+// [first [list 1 2 3]] ; returns 1
+fn first(args: &[Object]) -> Result<Object, CrotchetError> {
+  match &args[0] {
+    Object::ListData(l) => l.first().cloned().ok_or_else(|| {
+      CrotchetError::runtime("Cannot take `first` of an empty list")
+    }),
+    _ => Err(CrotchetError::type_error(
+      "First argument of `first` must be a list",
+    )),
+  }
+}
+
+// returns all elements of a list, without the first
+// [rest [list 1 2 3]] ; returns [2 3]
+fn rest(args: &[Object]) -> Result<Object, CrotchetError> {
+  match &args[0] {
+    Object::ListData(l) => {
+      Ok(Object::ListData(l.iter().skip(1).cloned().collect()))
+    }
+    _ => Err(CrotchetError::type_error(
+      "First argument of `rest` must be a list",
+    )),
+  }
+}
+
+// returns the length of a list
+// [len [list 1 2 3]] ; returns 3
+fn len(args: &[Object]) -> Result<Object, CrotchetError> {
+  match &args[0] {
+    #[allow(clippy::cast_precision_loss)]
+    Object::ListData(l) => Ok(Object::Number(l.len() as f64)),
+    _ => Err(CrotchetError::type_error(
+      "First argument of `len` must be a list",
+    )),
+  }
+}
+
+// [not true] ; returns false
+fn not(args: &[Object]) -> Result<Object, CrotchetError> {
+  match &args[0] {
+    Object::Bool(b) => Ok(Object::Bool(!b)),
+    _ => Err(CrotchetError::type_error(
+      "First argument of `not` must be a boolean",
+    )),
+  }
+}
+
+// of the form [exit code] where the code is optional, defaulting to 0
+// stops the whole program, with `code` as the process's exit status
+fn exit(args: &[Object]) -> Result<Object, CrotchetError> {
+  let code = match args {
+    [] => 0.0,
+    [Object::Number(n)] => *n,
+    [_] => {
+      return Err(CrotchetError::type_error(
+        "First argument of `exit` must be a number",
+      ))
+    }
+    _ => {
+      return Err(CrotchetError::arity(
+        "Invalid number of arguments for `exit`",
+      ))
+    }
+  };
+
+  if code.fract() != 0.0 || !(0.0..=255.0).contains(&code) {
+    return Err(CrotchetError::runtime(
+      "`exit` needs a whole number from 0 to 255",
+    ));
+  }
+  #[allow(clippy::cast_possible_truncation)]
+  Err(CrotchetError::Exit(code as i32))
+}
@@ -1,29 +1,43 @@
+use crate::builtins;
+use crate::error::CrotchetError;
 use crate::object::Object;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 #[derive(Debug, PartialEq, Default)]
 pub struct Env {
   parent: Option<Rc<RefCell<Env>>>,
-  vars: HashMap<String, Object>,
+  vars: HashMap<String, Binding>,
+  closed: bool, // its interpreter was dropped, see `close`
+}
+
+#[derive(Debug, PartialEq)]
+struct Binding {
+  val: Object,
+  constant: bool, // made with `const`, so `set` and `let` can't replace it
 }
 
 impl Env {
+  #[must_use]
   pub fn new() -> Self {
-    Env::default()
+    let mut env = Env::default();
+    builtins::register(&mut env);
+    env
   }
 
   pub fn extend(parent: Rc<RefCell<Self>>) -> Env {
     Env {
       vars: HashMap::new(),
       parent: Some(parent),
+      closed: false,
     }
   }
 
+  #[must_use]
   pub fn get(&self, name: &str) -> Option<Object> {
     match self.vars.get(name) {
-      Some(value) => Some(value.clone()),
+      Some(binding) => Some(binding.val.clone()),
       None => self
         .parent
         .as_ref()
@@ -31,7 +45,129 @@ impl Env {
     }
   }
 
+  // binds `name` in this frame no matter what was there, constant or not
   pub fn set(&mut self, name: &str, val: Object) {
-    self.vars.insert(name.to_string(), val);
+    let binding = Binding {
+      val,
+      constant: false,
+    };
+    self.vars.insert(name.to_string(), binding);
+  }
+
+  // like `set`, but programs can't change the binding afterwards
+  pub fn set_const(&mut self, name: &str, val: Object) {
+    let binding = Binding {
+      val,
+      constant: true,
+    };
+    self.vars.insert(name.to_string(), binding);
+  }
+
+  /// Binds `name` in this frame for `let` or `const`. Inner frames can still
+  /// shadow a constant, but not replace it in the frame that defined it.
+  ///
+  /// # Errors
+  ///
+  /// Returns a [`CrotchetError::Runtime`] if `name` is a constant here.
+  pub fn define(
+    &mut self,
+    name: &str,
+    val: Object,
+    constant: bool,
+  ) -> Result<(), CrotchetError> {
+    if self.vars.get(name).is_some_and(|b| b.constant) {
+      return Err(CrotchetError::runtime(format!(
+        "`{name}` is a constant, it can't be redefined"
+      )));
+    }
+    self
+      .vars
+      .insert(name.to_string(), Binding { val, constant });
+    Ok(())
+  }
+
+  #[must_use]
+  pub fn is_const(&self, name: &str) -> bool {
+    match self.vars.get(name) {
+      Some(binding) => binding.constant,
+      None => self
+        .parent
+        .as_ref()
+        .is_some_and(|p| p.borrow().is_const(name)),
+    }
+  }
+
+  /// Changes an existing binding in whichever frame defines it, unlike
+  /// `set`, which always binds in this one.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`CrotchetError::UnboundSymbol`] if no frame binds `name`, or a
+  /// [`CrotchetError::Runtime`] if the binding is a constant.
+  pub fn assign(
+    &mut self,
+    name: &str,
+    val: Object,
+  ) -> Result<(), CrotchetError> {
+    if let Some(binding) = self.vars.get_mut(name) {
+      if binding.constant {
+        return Err(CrotchetError::runtime(format!(
+          "`{name}` is a constant, it can't be `set`"
+        )));
+      }
+      binding.val = val;
+      return Ok(());
+    }
+    match &self.parent {
+      Some(parent) => parent.borrow_mut().assign(name, val),
+      None => Err(CrotchetError::unbound(name)),
+    }
+  }
+
+  // the names bound in this frame (not its parents), sorted by name
+  #[must_use]
+  pub fn bindings(&self) -> Vec<(String, Object)> {
+    let mut bindings: Vec<_> = self
+      .vars
+      .iter()
+      .map(|(name, binding)| (name.clone(), binding.val.clone()))
+      .collect();
+    bindings.sort_by(|a, b| a.0.cmp(&b.0));
+    bindings
+  }
+
+  // whether this env hangs off a global env that's been closed
+  pub(crate) fn is_closed(&self) -> bool {
+    self.closed || self.parent.as_ref().is_some_and(|p| p.borrow().is_closed())
+  }
+
+  // marks the global env `root` closed, then empties it and every closed env
+  // reachable from it through lambdas, lists and parent frames. a lambda
+  // keeps its defining env alive and that env usually holds the lambda, so
+  // `Rc` alone never frees them. envs of other interpreters that are still
+  // running are left alone
+  pub(crate) fn close(root: &Rc<RefCell<Env>>) {
+    root.borrow_mut().closed = true;
+    let mut seen = HashSet::new();
+    let mut envs = vec![root.clone()];
+    while let Some(env) = envs.pop() {
+      if !seen.insert(Rc::as_ptr(&env)) || !env.borrow().is_closed() {
+        continue;
+      }
+      let vars = std::mem::take(&mut env.borrow_mut().vars);
+      if let Some(parent) = &env.borrow().parent {
+        envs.push(parent.clone());
+      }
+
+      let mut values: Vec<Object> =
+        vars.into_values().map(|binding| binding.val).collect();
+      while let Some(val) = values.pop() {
+        match val {
+          Object::Lambda(lambda) => envs.push(lambda.env.clone()),
+          Object::ListData(items) => values.extend(items),
+          _ => {}
+        }
+      }
+    }
   }
 }
@@ -0,0 +1,283 @@
+use crate::lexer::{LexErr, Span};
+use crate::object::Object;
+use crate::parser::ParseError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+
+// everything that can go wrong while lexing, parsing or evaluating crotchet.
+// errors raised inside the evaluator start without a span and pick up the
+// location of the innermost form they pass through on the way out
+#[derive(Debug)]
+pub enum CrotchetError {
+  Lex(LexErr),
+  Parse(ParseError),
+  UnboundSymbol {
+    name: String,
+    span: Option<Span>,
+  },
+  Type {
+    msg: String,
+    span: Option<Span>,
+  },
+  Arity {
+    msg: String,
+    span: Option<Span>,
+  },
+  Runtime {
+    msg: String,
+    span: Option<Span>,
+  },
+  Io {
+    msg: String,
+    span: Option<Span>,
+  },
+  ResourceLimit {
+    msg: String,
+    span: Option<Span>,
+  },
+  // not really an error: the program called `exit` with this status. it
+  // unwinds like one so nothing after it runs
+  Exit(i32),
+  // an error raised inside lambda calls, with the calls innermost first
+  Traced {
+    error: Box<CrotchetError>,
+    frames: Vec<Frame>,
+  },
+}
+
+// a lambda call that was in progress, for backtraces
+#[derive(Debug, Clone)]
+pub struct Frame {
+  pub name: String,
+  pub span: Option<Span>, // where the call happened
+  pub args: Rc<[Object]>, // shared, so calls don't copy their args for this
+}
+
+impl fmt::Display for Frame {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "[{}", self.name)?;
+    for arg in self.args.iter() {
+      match arg {
+        // lambdas print their whole body, which is too much here
+        Object::Lambda(_) => write!(f, " fn")?,
+        Object::String(s) => write!(f, " {s:?}")?,
+        _ => write!(f, " {arg}")?,
+      }
+    }
+    write!(f, "]")
+  }
+}
+
+impl CrotchetError {
+  #[must_use]
+  pub fn unbound(name: &str) -> Self {
+    CrotchetError::UnboundSymbol {
+      name: name.to_string(),
+      span: None,
+    }
+  }
+
+  pub fn type_error(msg: impl Into<String>) -> Self {
+    CrotchetError::Type {
+      msg: msg.into(),
+      span: None,
+    }
+  }
+
+  pub fn arity(msg: impl Into<String>) -> Self {
+    CrotchetError::Arity {
+      msg: msg.into(),
+      span: None,
+    }
+  }
+
+  pub fn runtime(msg: impl Into<String>) -> Self {
+    CrotchetError::Runtime {
+      msg: msg.into(),
+      span: None,
+    }
+  }
+
+  pub fn io(msg: impl Into<String>) -> Self {
+    CrotchetError::Io {
+      msg: msg.into(),
+      span: None,
+    }
+  }
+
+  pub fn resource_limit(msg: impl Into<String>) -> Self {
+    CrotchetError::ResourceLimit {
+      msg: msg.into(),
+      span: None,
+    }
+  }
+
+  // the error itself, without any backtrace around it
+  #[must_use]
+  pub fn root(&self) -> &CrotchetError {
+    match self {
+      CrotchetError::Traced { error, .. } => error.root(),
+      _ => self,
+    }
+  }
+
+  // lambda calls the error passed through, innermost first
+  #[must_use]
+  pub fn trace(&self) -> &[Frame] {
+    match self {
+      CrotchetError::Traced { frames, .. } => frames,
+      _ => &[],
+    }
+  }
+
+  #[must_use]
+  pub fn span(&self) -> Option<Span> {
+    match self {
+      CrotchetError::Traced { error, .. } => error.span(),
+      CrotchetError::Exit(_) => None,
+      CrotchetError::Lex(e) => e.span.known(),
+      CrotchetError::Parse(e) => e.span.known(),
+      CrotchetError::UnboundSymbol { span, .. }
+      | CrotchetError::Type { span, .. }
+      | CrotchetError::Arity { span, .. }
+      | CrotchetError::Runtime { span, .. }
+      | CrotchetError::Io { span, .. }
+      | CrotchetError::ResourceLimit { span, .. } => *span,
+    }
+  }
+
+  // fills in the location if the error doesn't have one yet
+  #[must_use]
+  pub fn with_span(self, new_span: Span) -> Self {
+    let span = new_span.known();
+    match self {
+      CrotchetError::Traced { error, frames } => CrotchetError::Traced {
+        error: Box::new(error.with_span(new_span)),
+        frames,
+      },
+      CrotchetError::UnboundSymbol { name, span: None } => {
+        CrotchetError::UnboundSymbol { name, span }
+      }
+      CrotchetError::Type { msg, span: None } => {
+        CrotchetError::Type { msg, span }
+      }
+      CrotchetError::Arity { msg, span: None } => {
+        CrotchetError::Arity { msg, span }
+      }
+      CrotchetError::Runtime { msg, span: None } => {
+        CrotchetError::Runtime { msg, span }
+      }
+      CrotchetError::Io { msg, span: None } => CrotchetError::Io { msg, span },
+      CrotchetError::ResourceLimit { msg, span: None } => {
+        CrotchetError::ResourceLimit { msg, span }
+      }
+      other => other,
+    }
+  }
+
+  // forgets locations in source other than `source`, like the body of a
+  // function defined by an earlier `eval`, which can't be shown against the
+  // source that's being run now
+  #[must_use]
+  pub fn keep_spans_from(self, source: usize) -> Self {
+    let keep = |span: Option<Span>| span.filter(|span| span.source == source);
+    match self {
+      CrotchetError::Traced { error, mut frames } => {
+        for frame in &mut frames {
+          frame.span = keep(frame.span);
+        }
+        CrotchetError::Traced {
+          error: Box::new(error.keep_spans_from(source)),
+          frames,
+        }
+      }
+      CrotchetError::UnboundSymbol { name, span } => {
+        CrotchetError::UnboundSymbol {
+          name,
+          span: keep(span),
+        }
+      }
+      CrotchetError::Type { msg, span } => CrotchetError::Type {
+        msg,
+        span: keep(span),
+      },
+      CrotchetError::Arity { msg, span } => CrotchetError::Arity {
+        msg,
+        span: keep(span),
+      },
+      CrotchetError::Runtime { msg, span } => CrotchetError::Runtime {
+        msg,
+        span: keep(span),
+      },
+      CrotchetError::Io { msg, span } => CrotchetError::Io {
+        msg,
+        span: keep(span),
+      },
+      CrotchetError::ResourceLimit { msg, span } => {
+        CrotchetError::ResourceLimit {
+          msg,
+          span: keep(span),
+        }
+      }
+      other => other,
+    }
+  }
+
+  // wraps the error with the call stack (outermost first), unless it already
+  // has a trace from deeper down or is just an `exit`
+  #[must_use]
+  pub fn with_trace(self, frames: &[Frame]) -> Self {
+    if frames.is_empty()
+      || matches!(self, CrotchetError::Traced { .. } | CrotchetError::Exit(_))
+    {
+      return self;
+    }
+    CrotchetError::Traced {
+      error: Box::new(self),
+      frames: frames.iter().rev().cloned().collect(),
+    }
+  }
+}
+
+impl Error for CrotchetError {}
+
+impl fmt::Display for CrotchetError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CrotchetError::Lex(e) => write!(f, "{e}"),
+      CrotchetError::Parse(e) => write!(f, "{e}"),
+      CrotchetError::UnboundSymbol { name, .. } => {
+        write!(f, "Unbound symbol: {name}")
+      }
+      CrotchetError::Type { msg, .. } => write!(f, "type error: {msg}"),
+      CrotchetError::Arity { msg, .. } => write!(f, "arity error: {msg}"),
+      CrotchetError::Runtime { msg, .. } => write!(f, "{msg}"),
+      CrotchetError::Io { msg, .. } => write!(f, "io: {msg}"),
+      CrotchetError::ResourceLimit { msg, .. } => {
+        write!(f, "resource limit exceeded: {msg}")
+      }
+      CrotchetError::Exit(code) => write!(f, "exited with status {code}"),
+      CrotchetError::Traced { error, .. } => write!(f, "{error}"),
+    }
+  }
+}
+
+impl From<LexErr> for CrotchetError {
+  fn from(err: LexErr) -> Self {
+    CrotchetError::Lex(err)
+  }
+}
+
+impl From<ParseError> for CrotchetError {
+  fn from(err: ParseError) -> Self {
+    CrotchetError::Parse(err)
+  }
+}
+
+impl From<io::Error> for CrotchetError {
+  fn from(err: io::Error) -> Self {
+    CrotchetError::io(err.to_string())
+  }
+}
@@ -1,117 +1,350 @@
 use crate::env::Env;
-use crate::object::Object;
+use crate::error::{CrotchetError, Frame};
+use crate::lexer::Span;
+use crate::object::{Lambda, NativeFn, Object};
 use crate::parser::parse;
-use rand::Rng;
 use std::cell::RefCell;
-use std::io::{self, Write};
 use std::rc::Rc;
 
+// caps on what a program may use, for running code you don't trust.
+// `None` means unlimited
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+  pub depth: Option<usize>, // lambda calls in progress at once
+  pub steps: Option<u64>,   // forms evaluated over the whole run
+  pub list_len: Option<usize>,
+}
+
+/// How deep calls can nest under [`Limits::default`]. The Rust stack grows to
+/// fit however deep calls go, so without a cap a runaway recursion keeps
+/// allocating until memory runs out.
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+impl Default for Limits {
+  fn default() -> Self {
+    Limits {
+      depth: Some(DEFAULT_MAX_DEPTH),
+      steps: None,
+      list_len: None,
+    }
+  }
+}
+
+// each nested `eval_obj` gets at least this much Rust stack, and when it runs
+// low another chunk is allocated on the heap. cloning or dropping a list
+// recurses into its sublists without going through `eval_obj`, so this has
+// to fit that for lists nested as deep as the parser allows
+const STACK_RED_ZONE: usize = 4 * 1024 * 1024;
+const STACK_CHUNK: usize = 16 * 1024 * 1024;
+
+/// The names `eval` treats as syntax rather than looking them up, e.g. for a
+/// REPL to offer as completions alongside the bound names.
+pub const SPECIAL_FORMS: &[&str] = &[
+  "let", "const", "if", "cond", "when", "unless", "fn", "set", "while", "and",
+  "or", "do", "with",
+];
+
+/// Parses and evaluates each top-level form of `program` in `env`, returning
+/// the value of the last one.
+///
+/// # Errors
+///
+/// Returns the first error raised while parsing or evaluating.
 pub fn eval(
   program: &str,
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  let parsed_list = parse(program);
-  if parsed_list.is_err() {
-    return Err(format!("{}", parsed_list.err().unwrap()));
+) -> Result<Object, CrotchetError> {
+  eval_with_limits(program, env, Limits::default())
+}
+
+/// Like [`eval`], but stops with [`CrotchetError::ResourceLimit`] as soon as
+/// the program goes over `limits`.
+///
+/// # Errors
+///
+/// Returns the first error raised while parsing or evaluating.
+pub fn eval_with_limits(
+  program: &str,
+  env: &mut Rc<RefCell<Env>>,
+  limits: Limits,
+) -> Result<Object, CrotchetError> {
+  let forms = parse(program)?;
+  let mut ctx = Context {
+    limits,
+    ..Context::default()
+  };
+
+  let mut result = Object::Void;
+  for form in &forms {
+    // only lists can call into code from other source
+    result = match form {
+      Object::List(_, span) => eval_obj(form, env, &mut ctx)
+        .map_err(|e| e.keep_spans_from(span.source))?,
+      _ => eval_obj(form, env, &mut ctx)?,
+    };
   }
-  eval_obj(&parsed_list.unwrap(), env)
+  Ok(result)
+}
+
+// bookkeeping for one run of `eval` that doesn't belong in any env
+#[derive(Default)]
+struct Context {
+  frames: Vec<Frame>, // lambda calls in progress, outermost first
+  steps: u64,
+  limits: Limits,
+}
+
+impl Context {
+  fn step(&mut self) -> Result<(), CrotchetError> {
+    self.steps += 1;
+    match self.limits.steps {
+      Some(max) if self.steps > max => Err(CrotchetError::resource_limit(
+        format!("ran for more than {max} steps"),
+      )),
+      _ => Ok(()),
+    }
+  }
+
+  fn push_frame(&mut self, frame: Frame) -> Result<(), CrotchetError> {
+    match self.limits.depth {
+      Some(max) if self.frames.len() >= max => {
+        Err(CrotchetError::resource_limit(format!(
+          "calls nested more than {max} deep"
+        )))
+      }
+      _ => {
+        self.frames.push(frame);
+        Ok(())
+      }
+    }
+  }
+
+  fn check_list_len(&self, len: usize) -> Result<(), CrotchetError> {
+    match self.limits.list_len {
+      Some(max) if len > max => Err(CrotchetError::resource_limit(format!(
+        "list of {len} elements is longer than {max}"
+      ))),
+      _ => Ok(()),
+    }
+  }
+}
+
+// result of evaluating one form: either a finished value, or an expression in
+// tail position that should be evaluated in its place
+enum Step {
+  Done(Object),
+  Eval(Object, Rc<RefCell<Env>>),
+  // a lambda body, entering a call
+  Call(Rc<Vec<Object>>, Rc<RefCell<Env>>, Frame),
 }
 
 fn eval_obj(
   obj: &Object,
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  match obj {
-    Object::Void => Ok(Object::Void),
-    Object::Lambda(_params, _body) => Ok(Object::Void),
-    Object::Bool(_) => Ok(obj.clone()),
-    Object::Number(n) => Ok(Object::Number(*n)),
-    Object::Symbol(s) => eval_symbol(s, env),
-    Object::List(list) => eval_list(list, env),
-    Object::ListData(l) => Ok(Object::ListData(l.to_vec())),
-    Object::String(_) => Ok(obj.clone()),
+  ctx: &mut Context,
+) -> Result<Object, CrotchetError> {
+  let depth = ctx.frames.len();
+  let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_CHUNK, || {
+    eval_loop(obj, env, ctx)
+  });
+
+  // the innermost call that fails still has its frames on the stack, so it's
+  // the one that gets to record the backtrace
+  let result = if ctx.frames.len() > depth {
+    result.map_err(|e| e.with_trace(&ctx.frames))
+  } else {
+    result
+  };
+  ctx.frames.truncate(depth);
+  result
+}
+
+// forms in tail position (the branches of `if`, `cond`, `when` and `unless`,
+// and the last expr of `do`, `with` and lambda bodies) are handed back as a
+// `Step` and looped on here instead of recursing, so tail calls run in
+// constant Rust stack
+fn eval_loop(
+  obj: &Object,
+  env: &mut Rc<RefCell<Env>>,
+  ctx: &mut Context,
+) -> Result<Object, CrotchetError> {
+  let depth = ctx.frames.len();
+  let mut obj = obj.clone();
+  let mut env = env.clone();
+
+  loop {
+    ctx.step()?;
+    let step = match &obj {
+      Object::Void => Step::Done(Object::Void),
+      Object::Lambda(_)
+      | Object::NativeFn(_)
+      | Object::Bool(_)
+      | Object::String(_) => Step::Done(obj.clone()),
+      Object::Number(n) => Step::Done(Object::Number(*n)),
+      Object::Symbol(s, span) => {
+        Step::Done(eval_symbol(s, &mut env).map_err(|e| e.with_span(*span))?)
+      }
+      Object::List(list, span) => {
+        eval_list(list, &mut env, ctx).map_err(|e| e.with_span(*span))?
+      }
+      Object::ListData(l) => Step::Done(Object::ListData(l.clone())),
+    };
+
+    match step {
+      Step::Done(val) => return Ok(val),
+      Step::Eval(next_obj, next_env) => {
+        obj = next_obj;
+        env = next_env;
+      }
+      Step::Call(body, mut next_env, mut frame) => {
+        let span = match obj {
+          Object::List(_, span) => span,
+          _ => Span::default(),
+        };
+        frame.span = span.known();
+        // a call in tail position replaces the frame of the one making it
+        if ctx.frames.len() > depth {
+          ctx.frames.pop();
+        }
+        ctx.push_frame(frame).map_err(|e| e.with_span(span))?;
+
+        // the body is a sequence, like `do`, run inside the new frame. `fn`
+        // never makes an empty one, but embedders can
+        let Some((last, init)) = body.split_last() else {
+          return Ok(Object::Void);
+        };
+        for expr in init {
+          eval_obj(expr, &mut next_env, ctx)?;
+        }
+        obj = last.clone();
+        env = next_env;
+      }
+    }
   }
 }
 
-fn eval_symbol(s: &str, env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
+fn eval_symbol(
+  s: &str,
+  env: &mut Rc<RefCell<Env>>,
+) -> Result<Object, CrotchetError> {
   let val = env.borrow_mut().get(s);
   if val.is_none() {
-    return Err(format!("Unbound symbol: {}", s));
-  };
+    return Err(CrotchetError::unbound(s));
+  }
   Ok(val.unwrap().clone())
 }
 
 fn eval_list(
-  list: &Vec<Object>,
+  list: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  let head = &list[0];
+  ctx: &mut Context,
+) -> Result<Step, CrotchetError> {
+  let Some(head) = list.first() else {
+    return Ok(Step::Done(Object::List(
+      Rc::new(Vec::new()),
+      Span::default(),
+    )));
+  };
 
-  match head {
-    Object::Symbol(s) => match s.as_str() {
+  if let Object::Symbol(s, _) = head {
+    let val = match s.as_str() {
       "+" | "-" | "*" | "/" | "<" | "<=" | ">" | ">=" | "=" | "!=" => {
-        eval_binary_op(list, env) // returns
+        eval_binary_op(list, env, ctx)?
       }
 
-      "let" => eval_let(list, env),
-      "if" => eval_if(list, env),
-      "fn" => eval_function_definition(list),
-      "set" => eval_set(list, env),
-      "input" => eval_input(list, env),
-      "print" => eval_print(list, env),
-      "while" => eval_while(list, env),
-      "rand" => eval_rand(list, env),
-      "round" => eval_round(list, env),
-      "list" => eval_list_data(list, env),
-      "first" => eval_first(list, env),
-      "rest" => eval_rest(list, env),
-      "len" => eval_len(list, env),
-      // ^builtins go here
-      _ => eval_function_call(s, list, env),
-    },
-    _ => {
-      let mut new_list = Vec::new();
-      for obj in (*list).iter() {
-        let result = eval_obj(obj, env)?;
-        match result {
-          Object::Void => {}
-          _ => new_list.push(result),
-        }
-      }
+      "let" | "const" => eval_let(list, env, ctx)?,
+      "if" => return eval_if(list, env, ctx),
+      "cond" => return eval_cond(list, env, ctx),
+      "when" | "unless" => return eval_when(list, env, ctx),
+      "do" => return eval_sequence(&list[1..], env, ctx),
+      "with" => return eval_with(list, env, ctx),
+      "fn" => eval_function_definition(list, env)?,
+      "set" => eval_set(list, env, ctx)?,
+      "while" => eval_while(list, env, ctx)?,
+      "and" | "or" => eval_logical_op(list, env, ctx)?,
+      // ^special forms go here (and in `SPECIAL_FORMS`), builtin functions
+      // live in `builtins`
+      _ => return eval_application(head, list, env, ctx),
+    };
+    return Ok(Step::Done(val));
+  }
 
-      match &new_list.first() {
-        Some(Object::Lambda(_, _)) => {
-          eval_obj(&Object::List(Rc::new(new_list)), env)
-        }
-        _ => Ok(Object::List(Rc::new(new_list))),
-      }
+  eval_application(head, list, env, ctx)
+}
+
+fn eval_application(
+  head: &Object,
+  list: &[Object],
+  env: &mut Rc<RefCell<Env>>,
+  ctx: &mut Context,
+) -> Result<Step, CrotchetError> {
+  // anything else in head position is an expression, and if it evaluates to
+  // something callable we apply it to the rest of the list
+  let func = eval_obj(head, env, ctx)?;
+  if let Object::Lambda(lambda) = func {
+    let name = match head {
+      Object::Symbol(s, _) => s.clone(),
+      _ => "<lambda>".to_string(),
+    };
+    let args = eval_args(&list[1..], env, ctx)?;
+    return apply_lambda(name, &lambda, args, ctx);
+  }
+  if let Object::NativeFn(native) = func {
+    let args = eval_args(&list[1..], env, ctx)?;
+    return Ok(Step::Done(apply_native(&native, &args, ctx)?));
+  }
+  if let Object::Symbol(s, _) = head {
+    return Err(CrotchetError::type_error(format!(
+      "Not a lambda (`fn`): {s}"
+    )));
+  }
+
+  // not a call, so treat it as a list of expressions
+  let mut new_list = Vec::new();
+  if func != Object::Void {
+    new_list.push(func);
+  }
+  for obj in &list[1..] {
+    let result = eval_obj(obj, env, ctx)?;
+    match result {
+      Object::Void => {}
+      _ => new_list.push(result),
     }
   }
+  ctx.check_list_len(new_list.len())?;
+  Ok(Step::Done(Object::List(Rc::new(new_list), Span::default())))
 }
 
+// comparisons are exact, the same as Scheme's `=` on flonums
+#[allow(clippy::float_cmp)]
 fn eval_binary_op(
   list: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
+  ctx: &mut Context,
+) -> Result<Object, CrotchetError> {
   if list.len() != 3 {
-    return Err("Invalid number of arguments for binary operator".to_string());
+    return Err(CrotchetError::arity(
+      "Invalid number of arguments for binary operator",
+    ));
   }
 
   let operator = list[0].clone();
-  let left = eval_obj(&list[1].clone(), env)?;
-  let right = eval_obj(&list[2].clone(), env)?;
+  let left = eval_obj(&list[1].clone(), env, ctx)?;
+  let right = eval_obj(&list[2].clone(), env, ctx)?;
 
-  let left_val = match left {
-    Object::Number(n) => n,
-    _ => return Err(format!("Left operand must be a number {:?}", left)),
+  let Object::Number(left_val) = left else {
+    return Err(CrotchetError::type_error(format!(
+      "Left operand must be a number {left:?}"
+    )));
   };
-  let right_val = match right {
-    Object::Number(n) => n,
-    _ => return Err(format!("Right operand must be a number {:?}", right)),
+  let Object::Number(right_val) = right else {
+    return Err(CrotchetError::type_error(format!(
+      "Right operand must be a number {right:?}"
+    )));
   };
 
   match operator {
-    Object::Symbol(s) => match s.as_str() {
+    Object::Symbol(s, _) => match s.as_str() {
       "+" => Ok(Object::Number(left_val + right_val)),
       "-" => Ok(Object::Number(left_val - right_val)),
       "*" => Ok(Object::Number(left_val * right_val)),
@@ -123,330 +356,437 @@ fn eval_binary_op(
       "=" => Ok(Object::Bool(left_val == right_val)),
       "!=" => Ok(Object::Bool(left_val != right_val)),
       "%" => Ok(Object::Number(left_val % right_val)),
-      _ => Err(format!("Invalid binary operator: {}", s)),
+      _ => Err(CrotchetError::runtime(format!(
+        "Invalid binary operator: {s}"
+      ))),
     },
-    _ => Err("Operator must be a symbol".to_string()),
+    _ => Err(CrotchetError::type_error("Operator must be a symbol")),
   }
 }
 
 fn eval_let(
   list: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
+  ctx: &mut Context,
+) -> Result<Object, CrotchetError> {
+  // [const name value] is a `let` that can't be changed afterwards
+  let Object::Symbol(form, _) = &list[0] else {
+    unreachable!("`eval_list` only calls this for `let` and `const`")
+  };
   if list.len() != 3 {
-    return Err("Invalid number of arguments for `let`".to_string());
+    return Err(CrotchetError::arity(format!(
+      "Invalid number of arguments for `{form}`"
+    )));
   }
 
-  let sym = match &list[1] {
-    Object::Symbol(s) => s.clone(),
-    _ => return Err("Invalid `let`".to_string()),
+  let (sym, span) = match &list[1] {
+    Object::Symbol(s, span) => (s.clone(), *span),
+    _ => return Err(CrotchetError::type_error(format!("Invalid `{form}`"))),
   };
-  let val = eval_obj(&list[2], env)?;
-  env.borrow_mut().set(&sym, val);
+  let val = eval_obj(&list[2], env, ctx)?;
+  env
+    .borrow_mut()
+    .define(&sym, val, form == "const")
+    .map_err(|e| e.with_span(span))?;
   Ok(Object::Void)
 }
 
 fn eval_if(
   list: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  if list.len() != 4 {
-    return Err("Invalid number of arguments for `if`".to_string());
+  ctx: &mut Context,
+) -> Result<Step, CrotchetError> {
+  if list.len() != 3 && list.len() != 4 {
+    return Err(CrotchetError::arity("Invalid number of arguments for `if`"));
   }
 
-  let cond_obj = eval_obj(&list[1], env)?;
-  let cond = match cond_obj {
-    Object::Bool(b) => b,
-    _ => return Err("Condition must be a boolean".to_string()),
+  let Object::Bool(cond) = eval_obj(&list[1], env, ctx)? else {
+    return Err(CrotchetError::type_error("Condition must be a boolean"));
   };
 
-  if cond {
-    eval_obj(&list[2], env)
-  } else {
-    eval_obj(&list[3], env)
+  // a missing else branch just does nothing
+  match list.get(if cond { 2 } else { 3 }) {
+    Some(branch) => Ok(Step::Eval(branch.clone(), env.clone())),
+    None => Ok(Step::Done(Object::Void)),
   }
 }
 
-fn eval_function_definition(list: &[Object]) -> Result<Object, String> {
-  let params = match &list[1] {
-    Object::List(list) => {
-      let mut params = Vec::new();
-      for param in (*list).iter() {
-        match param {
-          Object::Symbol(s) => params.push(s.clone()),
-          _ => return Err("Invalid `fn` parameter".to_string()),
-        }
+// [cond [test expr...] ... [else expr...]]
+// runs the body of the first clause whose test is true, or of the `else`
+// clause if there is one. with no match it's `Void`
+fn eval_cond(
+  list: &[Object],
+  env: &mut Rc<RefCell<Env>>,
+  ctx: &mut Context,
+) -> Result<Step, CrotchetError> {
+  let clauses = &list[1..];
+  for (i, clause) in clauses.iter().enumerate() {
+    let (test, body, span) = match clause {
+      Object::List(items, span) if !items.is_empty() => {
+        (&items[0], &items[1..], *span)
+      }
+      _ => {
+        return Err(CrotchetError::type_error(
+          "Clauses of `cond` must be lists like [test expr...]",
+        ))
+      }
+    };
+
+    if matches!(test, Object::Symbol(s, _) if s == "else") {
+      if i != clauses.len() - 1 {
+        return Err(
+          CrotchetError::type_error("`else` must be the last clause of `cond`")
+            .with_span(span),
+        );
       }
-      params
+      return eval_sequence(body, env, ctx);
     }
-    _ => return Err("Invalid `fn`".to_string()),
-  };
 
-  let body = match &list[2] {
-    Object::List(list) => list.clone(),
-    _ => return Err("Invalid `fn`".to_string()),
-  };
+    let Object::Bool(cond) = eval_obj(test, env, ctx)? else {
+      return Err(
+        CrotchetError::type_error("Condition must be a boolean")
+          .with_span(span),
+      );
+    };
+    if cond {
+      return eval_sequence(body, env, ctx);
+    }
+  }
 
-  Ok(Object::Lambda(params, body.to_vec()))
+  Ok(Step::Done(Object::Void))
 }
 
-fn eval_function_call(
-  s: &str,
+// [when cond expr...] runs the exprs if cond is true, [unless cond expr...]
+// if it's false. otherwise they're `Void`
+fn eval_when(
   list: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  let lambda = env.borrow_mut().get(s);
-  if lambda.is_none() {
-    return Err(format!("Unbound symbol: {}", s));
-  }
-
-  let func = lambda.unwrap();
-  match func {
-    Object::Lambda(params, body) => {
-      // eeyikes rust your syntax is grody
-      let mut new_env = Rc::new(RefCell::new(Env::extend(env.clone())));
-      for (i, param) in params.iter().enumerate() {
-        let val = eval_obj(&list[i + 1], env)?;
-        new_env.borrow_mut().set(param, val);
-      }
-      eval_obj(&Object::List(Rc::new(body)), &mut new_env)
-    }
-    _ => Err(format!("Not a lambda (`fn`): {}", s)),
+  ctx: &mut Context,
+) -> Result<Step, CrotchetError> {
+  let Object::Symbol(form, _) = &list[0] else {
+    unreachable!("`eval_list` only calls this for `when` and `unless`")
+  };
+  if list.len() < 2 {
+    return Err(CrotchetError::arity(format!(
+      "Invalid number of arguments for `{form}`"
+    )));
+  }
+
+  let Object::Bool(cond) = eval_obj(&list[1], env, ctx)? else {
+    return Err(CrotchetError::type_error("Condition must be a boolean"));
+  };
+
+  if cond == (form == "when") {
+    eval_sequence(&list[2..], env, ctx)
+  } else {
+    Ok(Step::Done(Object::Void))
   }
 }
 
-// print: takes a variable list of args, printing each on a single line
-// after running, goes to new line and returns the number of things printed
-fn eval_print(
+// [with [[x 1] [y [+ x 1]]] body...]
+// binds each name in turn in a new frame, so later values can use earlier
+// names, then runs the body there. the names are gone once it's done
+fn eval_with(
   list: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  if list.len() <= 1 {
-    println!();
-    return Ok(Object::Number(0.0));
+  ctx: &mut Context,
+) -> Result<Step, CrotchetError> {
+  if list.len() < 2 {
+    return Err(CrotchetError::arity(
+      "Invalid number of arguments for `with`",
+    ));
   }
+  let Object::List(bindings, _) = &list[1] else {
+    return Err(CrotchetError::type_error(
+      "`with` needs a list of bindings like [[name value] ...]",
+    ));
+  };
 
-  for item in &list[1..] {
-    let val = eval_obj(item, env)?;
-    match val {
-      Object::Void => {}
-      Object::Number(n) => print!("{}", n),
-      Object::Bool(b) => print!("{}", b),
-      Object::Symbol(s) => print!("{}", s),
-      Object::Lambda(params, body) => {
-        print!("fn[");
-        for param in params {
-          print!("{} ", param);
-        }
-        print!("]");
-        for expr in body {
-          print!(" {}", expr);
+  let mut scope = Rc::new(RefCell::new(Env::extend(env.clone())));
+  for binding in bindings.iter() {
+    let (name, value) = match binding {
+      Object::List(pair, span) => match pair.as_slice() {
+        [Object::Symbol(name, _), value] => (name, value),
+        _ => {
+          return Err(
+            CrotchetError::type_error(
+              "Bindings of `with` must be like [name value]",
+            )
+            .with_span(*span),
+          )
         }
+      },
+      _ => {
+        return Err(CrotchetError::type_error(
+          "Bindings of `with` must be like [name value]",
+        ))
       }
-      _ => print!("{}", val),
-    }
+    };
+    let val = eval_obj(value, &mut scope, ctx)?;
+    scope.borrow_mut().set(name, val);
   }
 
-  println!();
-  Ok(Object::Number((list.len() - 1) as f64)) // TODO beware "as" conversion?
+  eval_sequence(&list[2..], &mut scope, ctx)
 }
 
-// of the form [input "prompt"] where the prompt is optional
-// prints "prompt" on a newline, accepts input from the user
-// tries to parse input as a float, will return err upon fail
-fn eval_input(
-  list: &[Object],
+// evaluates each expr in turn, leaving the last one to the trampoline so it's
+// in tail position. an empty sequence is `Void`
+fn eval_sequence(
+  exprs: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  if list.len() > 2 {
-    return Err("Invalid number of arguments for `get`".to_string());
-  }
-
-  let prompt = if list.len() == 2 {
-    let val = eval_obj(&list[1], env)?;
-    format!("{}", val)
-  } else {
-    "".to_string()
+  ctx: &mut Context,
+) -> Result<Step, CrotchetError> {
+  let Some((last, init)) = exprs.split_last() else {
+    return Ok(Step::Done(Object::Void));
   };
-  print!("{}", prompt);
-  match io::stdout().flush() {
-    Ok(_) => {}
-    Err(error) => {
-      return Err(format!("`get` failed to flush input: {}", error))
-    }
-  }
-
-  let mut input = String::new();
-  match io::stdin().read_line(&mut input) {
-    Ok(_) => {}
-    Err(error) => return Err(format!("`get` failed to read: {}", error)),
-  }
-
-  match input.trim().parse::<f64>() {
-    Ok(number) => Ok(Object::Number(number)),
-    Err(_) => Err(format!("`get` failed to parse {} as float", input)),
+  for expr in init {
+    eval_obj(expr, env, ctx)?;
   }
+  Ok(Step::Eval(last.clone(), env.clone()))
 }
 
-fn eval_set(
+fn eval_function_definition(
   list: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  if list.len() != 3 {
-    return Err("Invalid number of arguments for `set`".to_string());
+) -> Result<Object, CrotchetError> {
+  if list.len() < 3 {
+    return Err(CrotchetError::arity("Invalid number of arguments for `fn`"));
   }
 
-  let value = eval_obj(&list[2], env)?;
+  let Object::List(param_list, _) = &list[1] else {
+    return Err(CrotchetError::type_error("Invalid `fn`"));
+  };
+  let mut params = Vec::new();
+  let mut optional = Vec::new();
+  let mut rest = None;
 
-  match &list[1] {
-    Object::Symbol(s) => {
-      env.borrow_mut().set(s, value.clone());
-      Ok(value)
+  let mut iter = param_list.iter();
+  while let Some(param) = iter.next() {
+    match param {
+      // `&` takes one more name, for a list of the remaining args
+      Object::Symbol(s, _) if s == "&" => match (iter.next(), iter.next()) {
+        (Some(Object::Symbol(name, _)), None) if name != "&" => {
+          rest = Some(name.clone());
+        }
+        _ => {
+          return Err(CrotchetError::type_error(
+            "`&` in `fn` parameters must be followed by exactly one name",
+          ))
+        }
+      },
+      Object::Symbol(s, _) => {
+        if !optional.is_empty() {
+          return Err(CrotchetError::type_error(format!(
+            "`fn` parameter `{s}` needs a default, it comes after an \
+             optional parameter"
+          )));
+        }
+        params.push(s.clone());
+      }
+      // [name default]
+      Object::List(pair, span) => match pair.as_slice() {
+        [Object::Symbol(name, _), default] => {
+          optional.push((name.clone(), default.clone()));
+        }
+        _ => {
+          return Err(
+            CrotchetError::type_error(
+              "Optional `fn` parameters must be like [name default]",
+            )
+            .with_span(*span),
+          )
+        }
+      },
+      _ => return Err(CrotchetError::type_error("Invalid `fn` parameter")),
     }
-    _ => Err("First argument of `set` not symbol".to_string()),
   }
+
+  // capture the defining env so the body sees it after we return
+  Ok(Object::Lambda(Lambda {
+    params,
+    optional,
+    rest,
+    body: Rc::new(list[2..].to_vec()),
+    env: env.clone(),
+  }))
 }
 
-fn eval_while(
-  list: &[Object],
+fn eval_args(
+  args: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  if list.len() < 3 {
-    return Err("Invalid number of arguments for `while`".to_string());
-  }
+  ctx: &mut Context,
+) -> Result<Vec<Object>, CrotchetError> {
+  args.iter().map(|arg| eval_obj(arg, env, ctx)).collect()
+}
 
-  let condition = &list[1];
-  let body = &list[2..];
+fn apply_native(
+  native: &NativeFn,
+  args: &[Object],
+  ctx: &Context,
+) -> Result<Object, CrotchetError> {
+  if native.arity.is_some_and(|arity| arity != args.len()) {
+    return Err(CrotchetError::arity(format!(
+      "Invalid number of arguments for `{}`",
+      native.name
+    )));
+  }
 
-  let mut last_result = Object::Void;
+  let result = (native.func)(args)?;
+  if let Object::ListData(l) = &result {
+    ctx.check_list_len(l.len())?;
+  }
+  Ok(result)
+}
 
-  loop {
-    let cond_obj = eval_obj(condition, env)?;
-    let cond = match cond_obj {
-      Object::Bool(b) => b,
-      _ => {
-        return Err(
-          "Condition of `while` must evaluate to a boolean".to_string(),
-        )
-      }
+fn apply_lambda(
+  name: String,
+  lambda: &Lambda,
+  args: Vec<Object>,
+  ctx: &mut Context,
+) -> Result<Step, CrotchetError> {
+  // dropping an interpreter empties the envs its lambdas run in
+  if lambda.env.borrow().is_closed() {
+    return Err(CrotchetError::runtime(format!(
+      "`{name}` can't be called, the interpreter it was defined in was dropped"
+    )));
+  }
+  let required = lambda.params.len();
+  let max = required + lambda.optional.len();
+  if args.len() < required || (lambda.rest.is_none() && args.len() > max) {
+    let expected = match (lambda.rest.is_some(), max == required) {
+      (true, _) => format!("at least {required}"),
+      (false, true) => required.to_string(),
+      (false, false) => format!("{required} to {max}"),
     };
+    let last = if lambda.rest.is_some() { required } else { max };
+    let plural = if last == 1 { "" } else { "s" };
+    return Err(CrotchetError::arity(format!(
+      "`{name}` takes {expected} argument{plural}, but got {}",
+      args.len()
+    )));
+  }
 
-    if !cond {
-      break;
-    }
+  let args: Rc<[Object]> = args.into();
+  let frame = Frame {
+    name,
+    span: None,
+    args: args.clone(),
+  };
 
-    for body_expr in body {
-      last_result = eval_obj(body_expr, env)?;
-    }
+  // args are evaluated where the call happens, but the body runs in a
+  // frame on top of the env the lambda was defined in
+  // eeyikes rust your syntax is grody
+  let mut new_env = Rc::new(RefCell::new(Env::extend(lambda.env.clone())));
+  let mut args = args.iter().cloned();
+  for param in &lambda.params {
+    new_env
+      .borrow_mut()
+      .set(param, args.next().unwrap_or(Object::Void));
   }
-
-  Ok(last_result)
+  // defaults are evaluated in the new frame, so they can use earlier params
+  for (param, default) in &lambda.optional {
+    let val = match args.next() {
+      Some(val) => val,
+      None => eval_obj(default, &mut new_env, ctx)?,
+    };
+    new_env.borrow_mut().set(param, val);
+  }
+  if let Some(rest) = &lambda.rest {
+    new_env
+      .borrow_mut()
+      .set(rest, Object::ListData(args.collect()));
+  }
+  Ok(Step::Call(lambda.body.clone(), new_env, frame))
 }
 
-// of the form [rand x y]
-// returns a random number on the interval [x, y)
-fn eval_rand(
+fn eval_set(
   list: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
+  ctx: &mut Context,
+) -> Result<Object, CrotchetError> {
   if list.len() != 3 {
-    return Err("Invalid number of arguments for `rand`".to_string());
+    return Err(CrotchetError::arity(
+      "Invalid number of arguments for `set`",
+    ));
   }
 
-  let min = eval_obj(&list[1], env)?;
-  let max = eval_obj(&list[2], env)?;
+  let value = eval_obj(&list[2], env, ctx)?;
 
-  match (min, max) {
-    (Object::Number(i), Object::Number(j)) => {
-      let mut rng = rand::thread_rng();
-      let random_value: f64 = rng.gen_range(i..j);
-      Ok(Object::Number(random_value))
+  // `set` only changes variables that already exist, `let` makes them
+  match &list[1] {
+    Object::Symbol(s, span) => {
+      env
+        .borrow_mut()
+        .assign(s, value.clone())
+        .map_err(|e| e.with_span(*span))?;
+      Ok(value)
     }
-    (_, _) => Err("Invalid argument types for `rand`".to_string()),
-  }
-}
-
-fn eval_round(
-  list: &[Object],
-  env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  if list.len() != 2 {
-    return Err("Invalid number of arguments for `round`".to_string());
-  }
-
-  match eval_obj(&list[1], env) {
-    Ok(Object::Number(n)) => Ok(Object::Number(n.round())),
-    _ => Err("First argument of `set` must be a number".to_string()),
+    _ => Err(CrotchetError::type_error(
+      "First argument of `set` not symbol",
+    )),
   }
 }
 
-// evals an s-expression as a list
-fn eval_list_data(
+// [and a b ...] and [or a b ...] evaluate their operands left to right, but
+// stop as soon as the answer is known, so later operands may never run
+fn eval_logical_op(
   list: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  let mut new_list = Vec::new();
+  ctx: &mut Context,
+) -> Result<Object, CrotchetError> {
+  let Object::Symbol(op, _) = &list[0] else {
+    unreachable!("`eval_list` only calls this for `and` and `or`")
+  };
+  // the operand value that decides the result on its own
+  let decisive = op == "or";
 
-  for obj in list[1..].iter() {
-    new_list.push(eval_obj(obj, env)?);
+  for operand in &list[1..] {
+    let Object::Bool(b) = eval_obj(operand, env, ctx)? else {
+      return Err(CrotchetError::type_error(format!(
+        "Operands of `{op}` must be booleans"
+      )));
+    };
+    if b == decisive {
+      return Ok(Object::Bool(decisive));
+    }
   }
 
-  Ok(Object::ListData(new_list))
+  Ok(Object::Bool(!decisive))
 }
 
-// returns the first element of a list:
-// [first [list 1 2 3]] ; returns 1
-fn eval_first(
+fn eval_while(
   list: &[Object],
   env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  if list.len() != 2 {
-    return Err("Invalid number of arguments for `first`".to_string());
+  ctx: &mut Context,
+) -> Result<Object, CrotchetError> {
+  if list.len() < 3 {
+    return Err(CrotchetError::arity(
+      "Invalid number of arguments for `while`",
+    ));
   }
 
-  match eval_obj(&list[1], env) {
-    Ok(Object::ListData(l)) => Ok(l[0].clone()),
-    _ => Err("First argument of `first` must be a list".to_string()),
-  }
-}
+  let condition = &list[1];
+  let body = &list[2..];
 
-// returns all elements of a list, without the first
-// [rest [list 1 2 3]] ; returns [2 3]
-fn eval_rest(
-  list: &[Object],
-  env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  if list.len() != 2 {
-    return Err("Invalid number of arguments for `rest`".to_string());
-  }
+  let mut last_result = Object::Void;
 
-  let mut new_list = Vec::new();
+  loop {
+    let Object::Bool(cond) = eval_obj(condition, env, ctx)? else {
+      return Err(CrotchetError::type_error(
+        "Condition of `while` must evaluate to a boolean",
+      ));
+    };
 
-  match eval_obj(&list[1], env) {
-    Ok(Object::ListData(l)) => {
-      for item in l[1..].iter() {
-        new_list.push(item.clone());
-      }
-      Ok(Object::ListData(new_list))
+    if !cond {
+      break;
     }
-    _ => Err("First argument of `rest` must be a list".to_string()),
-  }
-}
 
-// returns the length of a list
-// [len [list 1 2 3]] ; returns 3
-fn eval_len(
-  list: &[Object],
-  env: &mut Rc<RefCell<Env>>,
-) -> Result<Object, String> {
-  if list.len() != 2 {
-    return Err("Invalid number of arguments for `len`".to_string());
+    for body_expr in body {
+      last_result = eval_obj(body_expr, env, ctx)?;
+    }
   }
 
-  match eval_obj(&list[1], env) {
-    Ok(Object::ListData(l)) => Ok(Object::Number(l.len() as f64)),
-    _ => Err("First argument of `rest` must be a list".to_string()),
-  }
+  Ok(last_result)
 }
 
 // TODO tests
@@ -454,6 +794,10 @@ fn eval_len(
 mod tests {
   use super::*;
 
+  fn list_of(items: Vec<Object>) -> Object {
+    Object::List(items.into(), Span::default())
+  }
+
   #[test]
   fn test_simple_add() {
     let mut env = Rc::new(RefCell::new(Env::new()));
@@ -470,10 +814,7 @@ mod tests {
                      [* pi [* r r]]
                    ]";
     let result = eval(program, &mut env).unwrap();
-    assert_eq!(
-      result,
-      Object::List(vec![Object::Number((3.14 * 10.0 * 10.0) as f64)].into())
-    );
+    assert_eq!(result, list_of(vec![Object::Number(3.14 * 10.0 * 10.0)]));
   }
 
   #[test]
@@ -485,10 +826,7 @@ mod tests {
                    ]
                   ";
     let result = eval(program, &mut env).unwrap();
-    assert_eq!(
-      result,
-      Object::List(vec![Object::Number((10 * 10) as f64)].into())
-    );
+    assert_eq!(result, list_of(vec![Object::Number(10.0 * 10.0)]));
   }
 
   #[test]
@@ -502,10 +840,7 @@ mod tests {
         ";
 
     let result = eval(program, &mut env).unwrap();
-    assert_eq!(
-      result,
-      Object::List(vec![Object::Number((89) as f64)].into())
-    );
+    assert_eq!(result, list_of(vec![Object::Number(89.0)]));
   }
 
   #[test]
@@ -519,10 +854,7 @@ mod tests {
         ";
 
     let result = eval(program, &mut env).unwrap();
-    assert_eq!(
-      result,
-      Object::List(vec![Object::Number((120) as f64)].into())
-    );
+    assert_eq!(result, list_of(vec![Object::Number(120.0)]));
   }
 
   #[test]
@@ -539,9 +871,776 @@ mod tests {
         ";
 
     let result = eval(program, &mut env).unwrap();
-    assert_eq!(
-      result,
-      Object::List(vec![Object::Number((3.14 * 10.0 * 10.0) as f64)].into())
-    );
+    assert_eq!(result, list_of(vec![Object::Number(3.14 * 10.0 * 10.0)]));
+  }
+
+  #[test]
+  fn test_closure_keeps_captured_env() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [
+              [let make-adder [fn [x] [fn [y] [+ x y]]]]
+              [let add5 [make-adder 5]]
+              [add5 10]
+            ]
+        ";
+
+    let result = eval(program, &mut env).unwrap();
+    assert_eq!(result, list_of(vec![Object::Number(15.0)]));
+  }
+
+  #[test]
+  fn test_lexical_scope() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [
+              [let x 1]
+              [let f [fn [] [+ x 0]]]
+              [let g [fn [x] [f]]]
+              [g 2]
+            ]
+        ";
+
+    let result = eval(program, &mut env).unwrap();
+    assert_eq!(result, list_of(vec![Object::Number(1.0)]));
+  }
+
+  #[test]
+  fn test_anonymous_fn_call() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let result = eval("[[fn [x] [* x x]] 5]", &mut env).unwrap();
+    assert_eq!(result, Object::Number(25.0));
+  }
+
+  #[test]
+  fn test_call_fn_from_list() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [
+              [let handlers [list [fn [e] [+ e 1]] [fn [e] [- e 1]]]]
+              [[first handlers] 41]
+            ]
+        ";
+
+    let result = eval(program, &mut env).unwrap();
+    assert_eq!(result, list_of(vec![Object::Number(42.0)]));
+  }
+
+  #[test]
+  fn test_call_non_fn_symbol() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    assert!(eval("[[let x 1] [x 2]]", &mut env).is_err());
+  }
+
+  #[test]
+  fn test_deep_tail_recursion() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [
+              [let count-down [fn [n] [if [= n 0] 0 [count-down [- n 1]]]]]
+              [count-down 100000]
+            ]
+        ";
+
+    let result = eval(program, &mut env).unwrap();
+    assert_eq!(result, list_of(vec![Object::Number(0.0)]));
+  }
+
+  #[test]
+  fn test_unbound_symbol_location() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let err = eval("[\n  [print [+ 1 foo]]\n]", &mut env).unwrap_err();
+    assert!(matches!(err, CrotchetError::UnboundSymbol { .. }));
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.col), (2, 15));
+  }
+
+  #[test]
+  fn test_type_error_location() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "[let f [fn [n] [if n 1 2]]]\n[f 3]";
+    let err = eval(&format!("[{program}]"), &mut env).unwrap_err();
+    assert!(matches!(err.root(), CrotchetError::Type { .. }));
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.col), (1, 17));
+  }
+
+  #[test]
+  fn test_backtrace() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [
+              [let inner [fn [x] [+ x true]]]
+              [let outer [fn [x] [- [inner [* x 2]] 1]]]
+              [outer 3]
+            ]
+        ";
+
+    let err = eval(program, &mut env).unwrap_err();
+    let trace: Vec<String> =
+      err.trace().iter().map(ToString::to_string).collect();
+    assert_eq!(trace, vec!["[inner 6]", "[outer 3]"]);
+    assert_eq!(err.trace()[1].span.unwrap().line, 5);
+  }
+
+  #[test]
+  fn test_depth_limit() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let limits = Limits {
+      depth: Some(50),
+      ..Limits::default()
+    };
+    let program = "
+            [
+              [let sum-to [fn [n] [if [= n 0] 0 [+ n [sum-to [- n 1]]]]]]
+              [let count-down [fn [n] [if [= n 0] 0 [count-down [- n 1]]]]]
+              [count-down 1000]
+              [sum-to 10]
+              [sum-to 100]
+            ]
+        ";
+
+    let err = eval_with_limits(program, &mut env, limits).unwrap_err();
+    assert!(matches!(err.root(), CrotchetError::ResourceLimit { .. }));
+    assert_eq!(err.trace().len(), 50);
+  }
+
+  #[test]
+  fn test_default_depth_limit() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [
+              [let sum-to [fn [n] [if [= n 0] 0 [+ n [sum-to [- n 1]]]]]]
+              [sum-to 5000]
+              [sum-to 100000]
+            ]
+        ";
+
+    let err = eval(program, &mut env).unwrap_err();
+    assert!(matches!(err.root(), CrotchetError::ResourceLimit { .. }));
+    assert_eq!(err.trace().len(), DEFAULT_MAX_DEPTH);
+  }
+
+  #[test]
+  fn test_deeply_nested_lists() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let depth = 999;
+    let program = "[list ".repeat(depth) + "1" + &"]".repeat(depth);
+    eval(&format!("[let x {program}]"), &mut env).unwrap();
+
+    // deep enough to need the stack grown, with `x` cloned and dropped there
+    let program = "
+            [let dig [fn [n] [if [= n 0] [len x] [+ 0 [dig [- n 1]]]]]]
+            [dig 2000]
+        ";
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Number(1.0));
+  }
+
+  #[test]
+  fn test_step_limit() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let limits = Limits {
+      steps: Some(10_000),
+      ..Limits::default()
+    };
+
+    let err = eval_with_limits("[while true 1]", &mut env, limits);
+    assert!(matches!(err, Err(CrotchetError::ResourceLimit { .. })));
+  }
+
+  #[test]
+  fn test_list_len_limit() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let limits = Limits {
+      list_len: Some(3),
+      ..Limits::default()
+    };
+
+    assert!(eval_with_limits("[list 1 2 3]", &mut env, limits).is_ok());
+    let err = eval_with_limits("[list 1 2 3 4]", &mut env, limits);
+    assert!(matches!(err, Err(CrotchetError::ResourceLimit { .. })));
+  }
+
+  #[test]
+  fn test_rand_bounds() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let Object::Number(n) = eval("[rand 1 2]", &mut env).unwrap() else {
+      panic!("rand should return a number");
+    };
+    assert!((1.0..2.0).contains(&n));
+
+    for program in [
+      "[rand 0 [/ 1 0]]",
+      "[rand [/ 0 0] 1]",
+      "[rand 2 1]",
+      "[rand 1 1]",
+      "[rand -1e308 1e308]",
+    ] {
+      let err = eval(program, &mut env).unwrap_err();
+      assert!(
+        matches!(err.root(), CrotchetError::Runtime { .. }),
+        "{program} gave {err:?}"
+      );
+    }
+  }
+
+  #[test]
+  fn test_builtins_are_values() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [
+              [let apply [fn [f x] [f x]]]
+              [apply round 2.6]
+              [let head first]
+              [head [list 7 8 9]]
+            ]
+        ";
+
+    let result = eval(program, &mut env).unwrap();
+    assert_eq!(
+      result,
+      list_of(vec![Object::Number(3.0), Object::Number(7.0)])
+    );
+  }
+
+  #[test]
+  fn test_shadow_builtin() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [
+              [let len [fn [l] [+ 1 0]]]
+              [len [list 1 2 3]]
+            ]
+        ";
+
+    let result = eval(program, &mut env).unwrap();
+    assert_eq!(result, list_of(vec![Object::Number(1.0)]));
+  }
+
+  #[test]
+  fn test_native_fn() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let double = NativeFn::new("double", Some(1), |args| match &args[0] {
+      Object::Number(n) => Ok(Object::Number(n * 2.0)),
+      _ => Err(CrotchetError::type_error("`double` needs a number")),
+    });
+    env.borrow_mut().set("double", Object::NativeFn(double));
+
+    let result = eval("[double 21]", &mut env).unwrap();
+    assert_eq!(result, Object::Number(42.0));
+    let err = eval("[double 1 2]", &mut env).unwrap_err();
+    assert!(matches!(err, CrotchetError::Arity { .. }));
+  }
+
+  #[test]
+  fn test_top_level_forms() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let r 10]
+            [let pi 3.14]
+            [* pi [* r r]]
+        ";
+
+    let result = eval(program, &mut env).unwrap();
+    assert_eq!(result, Object::Number(3.14 * 10.0 * 10.0));
+    assert_eq!(eval("", &mut env).unwrap(), Object::Void);
+    assert_eq!(eval("r", &mut env).unwrap(), Object::Number(10.0));
+  }
+
+  #[test]
+  fn test_exit() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let stop [fn [code] [exit code]]]
+            [let x 1]
+            [stop 3]
+            [set x 2]
+        ";
+
+    let err = eval(program, &mut env).unwrap_err();
+    // exiting from inside a call doesn't pick up a backtrace
+    assert!(matches!(err, CrotchetError::Exit(3)));
+    assert_eq!(env.borrow().get("x"), Some(Object::Number(1.0)));
+
+    let err = eval("[exit]", &mut env).unwrap_err();
+    assert!(matches!(err, CrotchetError::Exit(0)));
+    let err = eval("[exit 1.5]", &mut env).unwrap_err();
+    assert!(matches!(err.root(), CrotchetError::Runtime { .. }));
+    let err = eval("[exit 256]", &mut env).unwrap_err();
+    assert!(matches!(err.root(), CrotchetError::Runtime { .. }));
+  }
+
+  #[test]
+  fn test_and_or() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let cases = [
+      ("[and true true]", true),
+      ("[and true false true]", false),
+      ("[and]", true),
+      ("[or false false]", false),
+      ("[or false [< 1 2]]", true),
+      ("[or]", false),
+      ("[and [or false true] [not false]]", true),
+    ];
+    for (program, expected) in cases {
+      assert_eq!(
+        eval(program, &mut env).unwrap(),
+        Object::Bool(expected),
+        "{program}"
+      );
+    }
+  }
+
+  #[test]
+  fn test_and_or_short_circuit() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    // `nope` is unbound, so these only work if it's never evaluated
+    assert_eq!(
+      eval("[and false nope]", &mut env).unwrap(),
+      Object::Bool(false)
+    );
+    assert_eq!(
+      eval("[or true nope]", &mut env).unwrap(),
+      Object::Bool(true)
+    );
+    assert!(eval("[and true nope]", &mut env).is_err());
+  }
+
+  #[test]
+  fn test_boolean_operand_types() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    for program in ["[and true 1]", "[or false \"yes\"]", "[not 0]"] {
+      let err = eval(program, &mut env).unwrap_err();
+      assert!(
+        matches!(err.root(), CrotchetError::Type { .. }),
+        "{program}"
+      );
+    }
+  }
+
+  #[test]
+  fn test_if_without_else() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    assert_eq!(eval("[if true 1]", &mut env).unwrap(), Object::Number(1.0));
+    assert_eq!(eval("[if false 1]", &mut env).unwrap(), Object::Void);
+    assert!(eval("[if true]", &mut env).is_err());
+  }
+
+  #[test]
+  fn test_cond() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let sign [fn [n]
+              [cond [[< n 0] -1]
+                    [[= n 0] [let zero true] 0]
+                    [else 1]]]]
+            [list [sign -5] [sign 0] [sign 5]]
+        ";
+    assert_eq!(
+      eval(program, &mut env).unwrap(),
+      Object::ListData(vec![
+        Object::Number(-1.0),
+        Object::Number(0.0),
+        Object::Number(1.0)
+      ])
+    );
+
+    assert_eq!(eval("[cond [false 1]]", &mut env).unwrap(), Object::Void);
+    assert_eq!(eval("[cond]", &mut env).unwrap(), Object::Void);
+    // only the chosen clause runs
+    assert_eq!(
+      eval("[cond [true 1] [nope 2]]", &mut env).unwrap(),
+      Object::Number(1.0)
+    );
+  }
+
+  #[test]
+  fn test_cond_errors() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let programs = [
+      "[cond [else 1] [true 2]]",
+      "[cond 1]",
+      "[cond []]",
+      "[cond [1 2]]",
+    ];
+    for program in programs {
+      let err = eval(program, &mut env).unwrap_err();
+      assert!(
+        matches!(err.root(), CrotchetError::Type { .. }),
+        "{program}"
+      );
+    }
+
+    // points at the bad clause, not the whole `cond`
+    let err = eval("[cond [false 1]\n      [2 3]]", &mut env).unwrap_err();
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.col), (2, 7));
+  }
+
+  #[test]
+  fn test_when_unless() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let x 0]
+            [when true [set x [+ x 1]] [set x [+ x 1]] x]
+        ";
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Number(2.0));
+    assert_eq!(eval("[when false nope]", &mut env).unwrap(), Object::Void);
+    assert_eq!(
+      eval("[unless false 1]", &mut env).unwrap(),
+      Object::Number(1.0)
+    );
+    assert_eq!(eval("[unless true nope]", &mut env).unwrap(), Object::Void);
+    assert!(eval("[when 1 2]", &mut env).is_err());
+  }
+
+  #[test]
+  fn test_cond_tail_calls() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let count [fn [n]
+              [cond [[= n 0] 0]
+                    [else [when true [count [- n 1]]]]]]]
+            [count 100000]
+        ";
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Number(0.0));
+  }
+
+  #[test]
+  fn test_set_outer_variable() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let counter 0]
+            [let bump [fn [] [set counter [+ counter 1]]]]
+            [bump]
+            [bump]
+            counter
+        ";
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Number(2.0));
+  }
+
+  #[test]
+  fn test_set_closure_state() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let make-counter [fn [] [[fn [n] [fn [] [set n [+ n 1]]]] 0]]]
+            [let a [make-counter]]
+            [let b [make-counter]]
+            [a] [a] [b]
+            [list [a] [b]]
+        ";
+    assert_eq!(
+      eval(program, &mut env).unwrap(),
+      Object::ListData(vec![Object::Number(3.0), Object::Number(2.0)])
+    );
+  }
+
+  #[test]
+  fn test_set_shadowed_variable() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let x 1]
+            [let f [fn [x] [set x 10]]]
+            [f 5]
+            x
+        ";
+    // the parameter is the closest `x`, so the global one is untouched
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Number(1.0));
+  }
+
+  #[test]
+  fn test_set_unbound() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let err = eval("[let y 1]\n[set  x 2]", &mut env).unwrap_err();
+    assert!(matches!(err.root(), CrotchetError::UnboundSymbol { .. }));
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.col), (2, 7));
+    assert!(env.borrow().get("x").is_none());
+  }
+
+  #[test]
+  fn test_const() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [const limit 10]
+            [let under [fn [n] [< n limit]]]
+            [under 3]
+        ";
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Bool(true));
+    assert!(env.borrow().is_const("limit"));
+
+    for program in ["[set limit 11]", "[let limit 11]", "[const limit 11]"] {
+      let err = eval(program, &mut env).unwrap_err();
+      assert!(
+        matches!(err.root(), CrotchetError::Runtime { .. }),
+        "{program}"
+      );
+      // points at the name
+      let col = program.find("limit").unwrap() + 1;
+      assert_eq!(err.span().unwrap().col, col, "{program}");
+    }
+    assert_eq!(env.borrow().get("limit"), Some(Object::Number(10.0)));
+
+    // a `set` from inside a function is caught too
+    let err = eval("[[fn [] [set limit 0]]]", &mut env).unwrap_err();
+    assert!(matches!(err.root(), CrotchetError::Runtime { .. }));
+  }
+
+  #[test]
+  fn test_const_shadowing() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [const x 1]
+            [let f [fn [x] [set x [+ x 1]]]]
+            [f 5]
+        ";
+    // a parameter is a new variable that happens to have the same name
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Number(6.0));
+    assert_eq!(env.borrow().get("x"), Some(Object::Number(1.0)));
+  }
+
+  #[test]
+  fn test_do() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let x 1]
+            [do [set x [+ x 1]] [set x [* x 10]] [+ x 1]]
+        ";
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Number(21.0));
+    assert_eq!(eval("[do]", &mut env).unwrap(), Object::Void);
+    // `do` isn't a new scope
+    eval("[do [let y 5]]", &mut env).unwrap();
+    assert_eq!(env.borrow().get("y"), Some(Object::Number(5.0)));
+  }
+
+  #[test]
+  fn test_with() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let x 100]
+            [with [[x 1] [y [+ x 1]]]
+              [let z [* y 10]]
+              [+ x [+ y z]]]
+        ";
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Number(23.0));
+    // nothing leaks out, and the outer `x` is untouched
+    assert_eq!(env.borrow().get("x"), Some(Object::Number(100.0)));
+    assert!(env.borrow().get("y").is_none());
+    assert!(env.borrow().get("z").is_none());
+
+    assert_eq!(eval("[with []]", &mut env).unwrap(), Object::Void);
+    // `set` inside still reaches variables outside
+    eval("[with [[d 5]] [set x [+ x d]]]", &mut env).unwrap();
+    assert_eq!(env.borrow().get("x"), Some(Object::Number(105.0)));
+  }
+
+  #[test]
+  fn test_with_errors() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let programs = [
+      "[with]",
+      "[with x 1]",
+      "[with [x 1] x]",
+      "[with [[x]] x]",
+      "[with [[1 2]] 3]",
+      "[with [[x 1 2]] x]",
+    ];
+    for program in programs {
+      assert!(eval(program, &mut env).is_err(), "{program}");
+    }
+
+    let err = eval("[with [[a 1]\n       [b]] a]", &mut env).unwrap_err();
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.col), (2, 8));
+  }
+
+  #[test]
+  fn test_with_tail_calls() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let count [fn [n]
+              [if [= n 0] 0 [with [[m [- n 1]]] [do [count m]]]]]]
+            [count 100000]
+        ";
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Number(0.0));
+  }
+
+  #[test]
+  fn test_arity_errors() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    eval("[let add [fn [x y] [+ x y]]]", &mut env).unwrap();
+    let program =
+      "[let greet [fn [name [greeting \"hi\"]] [list greeting name]]]";
+    eval(program, &mut env).unwrap();
+    eval("[let tally [fn [x & xs] [len xs]]]", &mut env).unwrap();
+
+    let cases = [
+      ("[add 1]", "`add` takes 2 arguments, but got 1"),
+      ("[add 1 2 3]", "`add` takes 2 arguments, but got 3"),
+      ("[greet]", "`greet` takes 1 to 2 arguments, but got 0"),
+      ("[greet 1 2 3]", "`greet` takes 1 to 2 arguments, but got 3"),
+      ("[tally]", "`tally` takes at least 1 argument, but got 0"),
+      (
+        "[[fn [x] [+ x 1]]]",
+        "`<lambda>` takes 1 argument, but got 0",
+      ),
+    ];
+    for (program, msg) in cases {
+      let err = eval(program, &mut env).unwrap_err();
+      assert!(
+        matches!(err.root(), CrotchetError::Arity { .. }),
+        "{program}"
+      );
+      assert_eq!(err.to_string(), format!("arity error: {msg}"));
+      assert_eq!(err.span().unwrap().col, 1, "{program}");
+    }
+  }
+
+  #[test]
+  fn test_rest_params() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let args [fn [first-arg & others] [list first-arg others]]]
+            [list [args 1] [args 1 2 3]]
+        ";
+    let nums = |ns: &[f64]| {
+      Object::ListData(ns.iter().map(|n| Object::Number(*n)).collect())
+    };
+    assert_eq!(
+      eval(program, &mut env).unwrap(),
+      Object::ListData(vec![
+        Object::ListData(vec![Object::Number(1.0), nums(&[])]),
+        Object::ListData(vec![Object::Number(1.0), nums(&[2.0, 3.0])]),
+      ])
+    );
+
+    let program = "[[fn [& all] [len all]] 1 2 3 4]";
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Number(4.0));
+  }
+
+  #[test]
+  fn test_optional_params() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let calls 0]
+            [let area [fn [w [h w] [scale [do [set calls [+ calls 1]] 1]]]
+              [* scale [* w h]]]]
+            [list [area 3] [area 3 4] [area 3 4 10] calls]
+        ";
+    // defaults can use earlier params, and only run when they're needed
+    assert_eq!(
+      eval(program, &mut env).unwrap(),
+      Object::ListData(vec![
+        Object::Number(9.0),
+        Object::Number(12.0),
+        Object::Number(120.0),
+        Object::Number(2.0),
+      ])
+    );
+
+    let program = "[[fn [[a 1] & more] [list a more]] ]";
+    assert_eq!(
+      eval(program, &mut env).unwrap(),
+      Object::ListData(vec![Object::Number(1.0), Object::ListData(vec![])])
+    );
+  }
+
+  #[test]
+  fn test_bad_params() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let programs = [
+      "[fn [x &] [+ 1 1]]",
+      "[fn [& a b] [+ 1 1]]",
+      "[fn [& &] [+ 1 1]]",
+      "[fn [[a 1] b] [+ 1 1]]",
+      "[fn [[a]] [+ 1 1]]",
+      "[fn [[1 2]] [+ 1 1]]",
+      "[fn [1] [+ 1 1]]",
+    ];
+    for program in programs {
+      let err = eval(program, &mut env).unwrap_err();
+      assert!(
+        matches!(err.root(), CrotchetError::Type { .. }),
+        "{program}"
+      );
+      assert!(err.to_string().contains("param"), "{program}: {err}");
+    }
+  }
+
+  #[test]
+  fn test_multi_expression_body() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let log 0]
+            [let f [fn [x]
+              [set log [+ log 1]]
+              [let y [* x 2]]
+              [+ y 1]]]
+            [list [f 1] [f 5] log]
+        ";
+    assert_eq!(
+      eval(program, &mut env).unwrap(),
+      Object::ListData(vec![
+        Object::Number(3.0),
+        Object::Number(11.0),
+        Object::Number(2.0)
+      ])
+    );
+    // locals from the body stay in the call's frame
+    assert!(env.borrow().get("y").is_none());
+
+    // a body doesn't have to be a list
+    assert_eq!(
+      eval("[[fn [x] x] 7]", &mut env).unwrap(),
+      Object::Number(7.0)
+    );
+    assert!(eval("[fn [x]]", &mut env).is_err());
+  }
+
+  #[test]
+  fn test_multi_expression_body_tail_call() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let steps 0]
+            [let count [fn [n]
+              [set steps [+ steps 1]]
+              [if [= n 0] steps [count [- n 1]]]]]
+            [count 100000]
+        ";
+    assert_eq!(eval(program, &mut env).unwrap(), Object::Number(100_001.0));
+  }
+
+  #[test]
+  fn test_empty_lambda_body() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let lambda = Lambda {
+      params: Vec::new(),
+      optional: Vec::new(),
+      rest: None,
+      body: Rc::new(Vec::new()),
+      env: env.clone(),
+    };
+    env.borrow_mut().set("nothing", Object::Lambda(lambda));
+
+    assert_eq!(eval("[nothing]", &mut env).unwrap(), Object::Void);
+  }
+
+  #[test]
+  fn test_multi_expression_body_backtrace() {
+    let mut env = Rc::new(RefCell::new(Env::new()));
+    let program = "
+            [let f [fn [x]
+              [+ x nope]
+              x]]
+            [f 1]
+        ";
+    let err = eval(program, &mut env).unwrap_err();
+    let names: Vec<&str> = err
+      .trace()
+      .iter()
+      .map(|frame| frame.name.as_str())
+      .collect();
+    assert_eq!(names, vec!["f"]);
+    assert_eq!(err.span().unwrap().line, 3);
   }
 }
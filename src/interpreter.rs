@@ -0,0 +1,290 @@
+use crate::env::Env;
+use crate::error::CrotchetError;
+use crate::eval::{eval_with_limits, Limits};
+use crate::object::{NativeFn, Object};
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+/// A crotchet session for embedding: a global environment that programs are
+/// evaluated in one after another, so definitions carry over between calls.
+///
+/// ```
+/// use crotchet::Interpreter;
+///
+/// let mut interp = Interpreter::new();
+/// interp.set_global("radius", 2.0);
+/// interp.eval_str("[let area [* 3 [* radius radius]]]").unwrap();
+/// let area: f64 = interp.get_global("area").unwrap().try_into().unwrap();
+/// assert_eq!(area, 12.0);
+/// ```
+pub struct Interpreter {
+  env: Rc<RefCell<Env>>,
+  limits: Limits,
+}
+
+impl Default for Interpreter {
+  fn default() -> Self {
+    Interpreter::new()
+  }
+}
+
+impl Interpreter {
+  #[must_use]
+  pub fn new() -> Self {
+    Interpreter {
+      env: Rc::new(RefCell::new(Env::new())),
+      limits: Limits::default(),
+    }
+  }
+
+  /// An interpreter that stops any program going over `limits`.
+  #[must_use]
+  pub fn with_limits(limits: Limits) -> Self {
+    let mut interp = Interpreter::new();
+    interp.limits = limits;
+    interp
+  }
+
+  /// Evaluates crotchet source, returning the value of the program.
+  ///
+  /// # Errors
+  ///
+  /// Returns the first error raised while parsing or evaluating.
+  pub fn eval_str(&mut self, program: &str) -> Result<Object, CrotchetError> {
+    eval_with_limits(program, &mut self.env, self.limits)
+  }
+
+  /// Reads and evaluates a crotchet script.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`CrotchetError::Io`] if the file can't be read, otherwise the
+  /// first error raised while parsing or evaluating.
+  pub fn eval_file(
+    &mut self,
+    path: impl AsRef<Path>,
+  ) -> Result<Object, CrotchetError> {
+    let program = fs::read_to_string(path)?;
+    self.eval_str(&program)
+  }
+
+  /// Looks up a global binding, e.g. something a script defined with `let`.
+  #[must_use]
+  pub fn get_global(&self, name: &str) -> Option<Object> {
+    self.env.borrow().get(name)
+  }
+
+  /// Binds a global that programs can then refer to. A global bound with
+  /// [`Interpreter::set_const`] stays read-only to programs after this.
+  pub fn set_global(&mut self, name: &str, value: impl Into<Object>) {
+    let mut env = self.env.borrow_mut();
+    if env.is_const(name) {
+      env.set_const(name, value.into());
+    } else {
+      env.set(name, value.into());
+    }
+  }
+
+  /// Binds a read-only global: programs can use it, but `set` or `let` on it
+  /// is an error.
+  pub fn set_const(&mut self, name: &str, value: impl Into<Object>) {
+    self.env.borrow_mut().set_const(name, value.into());
+  }
+
+  /// Binds a Rust function that programs can call like a builtin. `arity` is
+  /// the exact number of args it takes, or `None` if `func` checks them.
+  pub fn register_fn(
+    &mut self,
+    name: &str,
+    arity: Option<usize>,
+    func: impl Fn(&[Object]) -> Result<Object, CrotchetError> + 'static,
+  ) {
+    let native = NativeFn::new(name, arity, func);
+    self.set_global(name, Object::NativeFn(native));
+  }
+
+  /// The global environment, for evaluating with [`crate::eval()`] directly.
+  /// It's emptied when the interpreter is dropped.
+  #[must_use]
+  pub fn env(&self) -> Rc<RefCell<Env>> {
+    self.env.clone()
+  }
+}
+
+// lambdas and the envs they're defined in point at each other, so without
+// this every interpreter that defined a function would leak its globals.
+// calling a lambda taken out of the interpreter is an error once it's gone
+impl Drop for Interpreter {
+  fn drop(&mut self) {
+    Env::close(&self.env);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_globals_persist_between_evals() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("[let sqr [fn [x] [* x x]]]").unwrap();
+    let result = interp.eval_str("[sqr 7]").unwrap();
+    assert_eq!(result, Object::Number(49.0));
+  }
+
+  #[test]
+  fn test_spans_from_earlier_evals() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("[let f [fn [x] [+ x true]]]").unwrap();
+
+    let err = interp
+      .eval_str("[print \"a long string here..\" [f 1]]")
+      .unwrap_err();
+    // the failing `+` is in the first program, so it has no place in this one
+    assert!(err.span().is_none());
+    let span = err.trace()[0].span.unwrap();
+    assert_eq!((span.line, span.col), (1, 31));
+  }
+
+  #[test]
+  fn test_set_and_get_globals() {
+    let mut interp = Interpreter::new();
+    interp.set_global("names", vec!["ada", "grace"]);
+    interp.set_global("verbose", true);
+    interp.eval_str("[let first-name [first names]]").unwrap();
+
+    let name: String =
+      interp.get_global("first-name").unwrap().try_into().unwrap();
+    assert_eq!(name, "ada");
+    let names: Vec<String> =
+      interp.get_global("names").unwrap().try_into().unwrap();
+    assert_eq!(names, vec!["ada", "grace"]);
+    assert!(interp.get_global("missing").is_none());
+  }
+
+  #[test]
+  fn test_conversion_type_error() {
+    let mut interp = Interpreter::new();
+    interp.set_global("n", 1.0);
+    let err = bool::try_from(interp.get_global("n").unwrap()).unwrap_err();
+    assert!(matches!(err, CrotchetError::Type { .. }));
+  }
+
+  #[test]
+  fn test_register_fn() {
+    let mut interp = Interpreter::new();
+    interp.register_fn("sum", None, |args| {
+      let mut total = 0.0;
+      for arg in args {
+        total += f64::try_from(arg.clone())?;
+      }
+      Ok(Object::Number(total))
+    });
+
+    let result = interp.eval_str("[sum 1 2 3 4]").unwrap();
+    assert_eq!(result, Object::Number(10.0));
+    assert!(interp.eval_str("[sum 1 true]").is_err());
+  }
+
+  #[test]
+  fn test_global_bindings() {
+    let mut interp = Interpreter::new();
+    interp
+      .eval_str("[let b 2] [let a [fn [] [+ 1 1]]]")
+      .unwrap();
+    let env = interp.env();
+    let bindings = env.borrow().bindings();
+    let names: Vec<&str> = bindings.iter().map(|(n, _)| n.as_str()).collect();
+
+    assert!(names.contains(&"print"));
+    let a = names.iter().position(|n| *n == "a").unwrap();
+    assert_eq!(names[a + 1], "b");
+    assert_eq!(bindings[a + 1].1, Object::Number(2.0));
+  }
+
+  #[test]
+  fn test_set_const() {
+    let mut interp = Interpreter::new();
+    interp.set_const("version", "1.2");
+    let version: String =
+      interp.eval_str("version").unwrap().try_into().unwrap();
+    assert_eq!(version, "1.2");
+    assert!(interp.eval_str("[set version \"2.0\"]").is_err());
+    assert!(interp.eval_str("[let version \"2.0\"]").is_err());
+
+    // the embedder can still change it
+    interp.set_const("version", "1.3");
+    assert_eq!(interp.get_global("version"), Some("1.3".into()));
+    // without making it writable again
+    interp.set_global("version", "1.4");
+    assert_eq!(interp.get_global("version"), Some("1.4".into()));
+    assert!(interp.eval_str("[set version \"2.0\"]").is_err());
+  }
+
+  #[test]
+  fn test_drop_frees_envs() {
+    let mut interp = Interpreter::new();
+    interp
+      .eval_str(
+        "[let fact [fn [n] [if [<= n 1] 1 [* n [fact [- n 1]]]]]]
+         [let make-counter [fn [] [with [[n 0]] [fn [] [set n [+ n 1]]]]]]
+         [let counter [make-counter]]
+         [counter]",
+      )
+      .unwrap();
+    let global = Rc::downgrade(&interp.env());
+    let Some(Object::Lambda(counter)) = interp.get_global("counter") else {
+      panic!("counter should be a lambda");
+    };
+    let inner = Rc::downgrade(&counter.env);
+    drop(counter);
+
+    drop(interp);
+    assert!(global.upgrade().is_none());
+    assert!(inner.upgrade().is_none());
+  }
+
+  #[test]
+  fn test_drop_leaves_other_interpreters() {
+    let mut a = Interpreter::new();
+    a.eval_str("[let secret 42] [let get [fn [] secret]]")
+      .unwrap();
+
+    let mut b = Interpreter::new();
+    b.set_global("g", a.get_global("get").unwrap());
+    assert_eq!(b.eval_str("[g]").unwrap(), Object::Number(42.0));
+    drop(b);
+
+    assert_eq!(a.eval_str("[get]").unwrap(), Object::Number(42.0));
+    assert!(a.eval_str("[print 1]").is_ok());
+  }
+
+  #[test]
+  fn test_lambda_outliving_interpreter() {
+    let mut a = Interpreter::new();
+    a.eval_str("[let secret 42] [let get [fn [] secret]]")
+      .unwrap();
+
+    let mut b = Interpreter::new();
+    b.set_global("g", a.get_global("get").unwrap());
+    drop(a);
+
+    let err = b.eval_str("[g]").unwrap_err();
+    assert!(matches!(err.root(), CrotchetError::Runtime { .. }));
+    assert!(err.to_string().contains("dropped"), "{err}");
+    // b itself still works
+    assert!(b.eval_str("[print 1]").is_ok());
+  }
+
+  #[test]
+  fn test_limits() {
+    let limits = Limits {
+      steps: Some(100),
+      ..Limits::default()
+    };
+    let mut interp = Interpreter::with_limits(limits);
+    assert!(interp.eval_str("[while true 1]").is_err());
+  }
+}
@@ -1,8 +1,52 @@
 use std::error::Error;
 use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// where something starts in the source, 1-based, and how many chars it covers
+// on that line. a line of 0 means the location is unknown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+  pub line: usize,
+  pub col: usize,
+  pub len: usize,
+  // which `tokenize` call it came from, since a function defined by one
+  // `eval` can fail while running in a later one
+  pub source: usize,
+}
+
+// 0 is left for spans that weren't made by the lexer
+static NEXT_SOURCE: AtomicUsize = AtomicUsize::new(1);
+
+impl Span {
+  #[must_use]
+  pub fn new(line: usize, col: usize, len: usize, source: usize) -> Self {
+    Span {
+      line,
+      col,
+      len,
+      source,
+    }
+  }
+
+  // `None` for spans of things that didn't come from source text
+  #[must_use]
+  pub fn known(self) -> Option<Span> {
+    if self.line == 0 {
+      None
+    } else {
+      Some(self)
+    }
+  }
+}
+
+impl fmt::Display for Span {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:{}", self.line, self.col)
+  }
+}
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Token {
+pub enum TokenKind {
   Number(f64),
   Symbol(String),
   LBracket,
@@ -10,20 +54,42 @@ pub enum Token {
   StringLit(String),
 }
 
-impl fmt::Display for Token {
+impl fmt::Display for TokenKind {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
-      Token::Number(n) => write!(f, "{n}"),
-      Token::Symbol(s) | Token::StringLit(s) => write!(f, "{s}"),
-      Token::LBracket => write!(f, "["),
-      Token::RBracket => write!(f, "]"),
+      TokenKind::Number(n) => write!(f, "{n}"),
+      TokenKind::Symbol(s) | TokenKind::StringLit(s) => write!(f, "{s}"),
+      TokenKind::LBracket => write!(f, "["),
+      TokenKind::RBracket => write!(f, "]"),
     }
   }
 }
 
+#[derive(Debug, Clone)]
+pub struct Token {
+  pub kind: TokenKind,
+  pub span: Span,
+}
+
+// the span is just bookkeeping, the same token lexed somewhere else is equal
+impl PartialEq for Token {
+  fn eq(&self, other: &Self) -> bool {
+    self.kind == other.kind
+  }
+}
+
+impl fmt::Display for Token {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.kind)
+  }
+}
+
 #[derive(Debug)]
 pub struct LexErr {
-  msg: String,
+  pub msg: String,
+  pub span: Span,
+  // the source ran out part way through a token, so more could fix it
+  pub at_eof: bool,
 }
 
 impl Error for LexErr {}
@@ -34,87 +100,227 @@ impl fmt::Display for LexErr {
   }
 }
 
-#[allow(clippy::unnecessary_wraps)]
+// walks the source a char at a time, keeping track of where it is
+struct Cursor<'a> {
+  chars: std::iter::Peekable<std::str::Chars<'a>>,
+  line: usize,
+  col: usize,
+  source: usize,
+}
+
+impl<'a> Cursor<'a> {
+  fn new(source: &'a str) -> Self {
+    Cursor {
+      chars: source.chars().peekable(),
+      line: 1,
+      col: 1,
+      source: NEXT_SOURCE.fetch_add(1, Ordering::Relaxed),
+    }
+  }
+
+  fn peek(&mut self) -> Option<char> {
+    self.chars.peek().copied()
+  }
+
+  fn next(&mut self) -> Option<char> {
+    let c = self.chars.next()?;
+    if c == '\n' {
+      self.line += 1;
+      self.col = 1;
+    } else {
+      self.col += 1;
+    }
+    Some(c)
+  }
+
+  fn span(&self, len: usize) -> Span {
+    Span::new(self.line, self.col, len, self.source)
+  }
+}
+
+/// Splits crotchet source into tokens.
+///
+/// # Errors
+///
+/// Returns a [`LexErr`] for an unterminated string or a bad escape sequence.
 pub fn tokenize(source: &str) -> Result<Vec<Token>, LexErr> {
-  // split into lines to handle comments
-  let lines = source.lines();
+  let mut cursor = Cursor::new(source);
   let mut tokens: Vec<Token> = Vec::new();
 
-  // clean comment from line
-  for line in lines {
-    // inline comment starts with a semicolon!
-    let clean_line = match line.split_once(';') {
-      Some((before, _)) => before,
-      None => line,
-    };
+  // a `#!` line lets scripts be run as executables, it's not crotchet
+  if source.starts_with("#!") {
+    while cursor.peek().is_some_and(|c| c != '\n') {
+      cursor.next();
+    }
+  }
 
-    // now tokenize
-    let temp = clean_line.replace('[', " [ ").replace(']', " ] ");
+  while let Some(c) = cursor.peek() {
+    if c.is_whitespace() {
+      cursor.next();
+      continue;
+    }
 
-    let mut chars = temp.chars().peekable();
-    while let Some(c) = chars.next() {
-      if c.is_whitespace() {
-        continue;
+    // inline comment starts with a semicolon and runs to the end of the line
+    if c == ';' {
+      while cursor.peek().is_some_and(|c| c != '\n') {
+        cursor.next();
       }
+      continue;
+    }
 
-      match c {
-        '[' => tokens.push(Token::LBracket),
-        ']' => tokens.push(Token::RBracket),
-        '"' => {
-          // start parsing string literal
-          let mut literal = String::new();
-          while let Some(&next_char) = chars.peek() {
-            if next_char == '"' {
-              chars.next(); // consume closing quote
-              break;
-            }
-            literal.push(next_char);
-            chars.next();
+    let start = cursor.span(0);
+    let kind = match c {
+      '[' => {
+        cursor.next();
+        TokenKind::LBracket
+      }
+      ']' => {
+        cursor.next();
+        TokenKind::RBracket
+      }
+      '"' => TokenKind::StringLit(lex_string(&mut cursor)?),
+      _ => {
+        // symbols and numbers
+        let mut word = String::new();
+        while let Some(next_char) = cursor.peek() {
+          if next_char.is_whitespace() || "[]\";".contains(next_char) {
+            break;
           }
-          tokens.push(Token::StringLit(literal));
+          word.push(next_char);
+          cursor.next();
         }
-        _ => {
-          // symbols and numbers
-          let mut word = c.to_string();
-          while let Some(&next_char) = chars.peek() {
-            if next_char.is_whitespace()
-              || next_char == '['
-              || next_char == ']'
-              || next_char == '"'
-            {
-              break;
-            }
-            word.push(next_char);
-            chars.next();
-          }
-          if let Ok(number) = word.parse::<f64>() {
-            tokens.push(Token::Number(number));
-          } else {
-            tokens.push(Token::Symbol(word));
-          }
+        if let Ok(number) = word.parse::<f64>() {
+          TokenKind::Number(number)
+        } else {
+          TokenKind::Symbol(word)
         }
       }
-    }
+    };
+
+    // strings can run over several lines, but a span only covers one, so
+    // those just point at the opening quote
+    let len = if cursor.line == start.line {
+      cursor.col - start.col
+    } else {
+      1
+    };
+    tokens.push(Token {
+      kind,
+      span: Span { len, ..start },
+    });
   }
 
   Ok(tokens)
 }
 
+/// Whether `source` stops part way through a form: a `[` is still open or a
+/// string is unterminated. Other errors are left for [`tokenize`] to report.
+#[must_use]
+pub fn is_incomplete(source: &str) -> bool {
+  match tokenize(source) {
+    Ok(tokens) => {
+      let mut depth = 0usize;
+      for token in tokens {
+        match token.kind {
+          TokenKind::LBracket => depth += 1,
+          TokenKind::RBracket => depth = depth.saturating_sub(1),
+          _ => {}
+        }
+      }
+      depth > 0
+    }
+    Err(err) => err.at_eof,
+  }
+}
+
+// reads a string literal, from its opening quote to its closing one
+fn lex_string(cursor: &mut Cursor) -> Result<String, LexErr> {
+  let open = cursor.span(1);
+  cursor.next();
+
+  let mut literal = String::new();
+  loop {
+    let escape = cursor.span(2);
+    match cursor.next() {
+      Some('"') => return Ok(literal),
+      Some('\\') => literal.push(lex_escape(cursor, escape)?),
+      Some(c) => literal.push(c),
+      None => {
+        return Err(LexErr {
+          msg: "Unterminated string, expected a closing `\"`".to_string(),
+          span: open,
+          at_eof: true,
+        })
+      }
+    }
+  }
+}
+
+// the char for an escape sequence, with the backslash already read
+fn lex_escape(cursor: &mut Cursor, span: Span) -> Result<char, LexErr> {
+  let bad_escape = |msg: &str| LexErr {
+    msg: msg.to_string(),
+    span,
+    at_eof: false,
+  };
+
+  match cursor.next() {
+    Some('"') => Ok('"'),
+    Some('\\') => Ok('\\'),
+    Some('n') => Ok('\n'),
+    Some('t') => Ok('\t'),
+    Some('r') => Ok('\r'),
+    Some('0') => Ok('\0'),
+    Some('u') => {
+      if cursor.next() != Some('{') {
+        return Err(bad_escape("Expected `{` after `\\u`"));
+      }
+      let mut hex = String::new();
+      loop {
+        match cursor.next() {
+          Some('}') => break,
+          Some(c) if c.is_ascii_hexdigit() && hex.len() < 6 => hex.push(c),
+          _ => {
+            return Err(bad_escape(
+              "Expected up to 6 hex digits and a `}` in `\\u{...}`",
+            ))
+          }
+        }
+      }
+      u32::from_str_radix(&hex, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| {
+          bad_escape(&format!("`\\u{{{hex}}}` is not a valid unicode char"))
+        })
+    }
+    Some(c) => Err(bad_escape(&format!("Unknown escape sequence `\\{c}`"))),
+    None => Err(LexErr {
+      at_eof: true,
+      ..bad_escape("Unterminated string, expected a closing `\"`")
+    }),
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
 
+  fn kinds(tokens: Vec<Token>) -> Vec<TokenKind> {
+    tokens.into_iter().map(|t| t.kind).collect()
+  }
+
   #[test]
   fn test_add() {
     let tokens = tokenize("[+ 1 2]").unwrap_or(vec![]);
     assert_eq!(
-      tokens,
+      kinds(tokens),
       vec![
-        Token::LBracket,
-        Token::Symbol("+".to_string()),
-        Token::Number(1.0),
-        Token::Number(2.0),
-        Token::RBracket,
+        TokenKind::LBracket,
+        TokenKind::Symbol("+".to_string()),
+        TokenKind::Number(1.0),
+        TokenKind::Number(2.0),
+        TokenKind::RBracket,
       ]
     );
   }
@@ -123,13 +329,13 @@ mod tests {
   fn test_string_literal() {
     let tokens = tokenize("[puts \"hello \" \"world!\"]").unwrap_or(vec![]);
     assert_eq!(
-      tokens,
+      kinds(tokens),
       vec![
-        Token::LBracket,
-        Token::Symbol("puts".to_string()),
-        Token::StringLit("hello ".to_string()),
-        Token::StringLit("world!".to_string()),
-        Token::RBracket,
+        TokenKind::LBracket,
+        TokenKind::Symbol("puts".to_string()),
+        TokenKind::StringLit("hello ".to_string()),
+        TokenKind::StringLit("world!".to_string()),
+        TokenKind::RBracket,
       ]
     );
   }
@@ -147,30 +353,176 @@ mod tests {
     let tokens = tokenize(program).unwrap_or(vec![]);
 
     assert_eq!(
-      tokens,
+      kinds(tokens),
       vec![
-        Token::LBracket,
-        Token::LBracket,
-        Token::Symbol("let".to_string()),
-        Token::Symbol("r".to_string()),
-        Token::Number(10.0),
-        Token::RBracket,
-        Token::LBracket,
-        Token::Symbol("let".to_string()),
-        Token::Symbol("pi".to_string()),
-        Token::Number(3.14),
-        Token::RBracket,
-        Token::LBracket,
-        Token::Symbol("*".to_string()),
-        Token::Symbol("pi".to_string()),
-        Token::LBracket,
-        Token::Symbol("*".to_string()),
-        Token::Symbol("r".to_string()),
-        Token::Symbol("r".to_string()),
-        Token::RBracket,
-        Token::RBracket,
-        Token::RBracket
+        TokenKind::LBracket,
+        TokenKind::LBracket,
+        TokenKind::Symbol("let".to_string()),
+        TokenKind::Symbol("r".to_string()),
+        TokenKind::Number(10.0),
+        TokenKind::RBracket,
+        TokenKind::LBracket,
+        TokenKind::Symbol("let".to_string()),
+        TokenKind::Symbol("pi".to_string()),
+        TokenKind::Number(3.14),
+        TokenKind::RBracket,
+        TokenKind::LBracket,
+        TokenKind::Symbol("*".to_string()),
+        TokenKind::Symbol("pi".to_string()),
+        TokenKind::LBracket,
+        TokenKind::Symbol("*".to_string()),
+        TokenKind::Symbol("r".to_string()),
+        TokenKind::Symbol("r".to_string()),
+        TokenKind::RBracket,
+        TokenKind::RBracket,
+        TokenKind::RBracket
       ]
     );
   }
+
+  #[test]
+  fn test_spans() {
+    let tokens = tokenize("[let x 10]\n  [print \"hi\" x]").unwrap();
+    let spans: Vec<(usize, usize, usize)> = tokens
+      .iter()
+      .map(|t| (t.span.line, t.span.col, t.span.len))
+      .collect();
+    assert_eq!(
+      spans,
+      vec![
+        (1, 1, 1),
+        (1, 2, 3),
+        (1, 6, 1),
+        (1, 8, 2),
+        (1, 10, 1),
+        (2, 3, 1),
+        (2, 4, 5),
+        (2, 10, 4),
+        (2, 15, 1),
+        (2, 16, 1),
+      ]
+    );
+  }
+
+  fn lex_one(source: &str) -> TokenKind {
+    let mut tokens = kinds(tokenize(source).unwrap());
+    assert_eq!(tokens.len(), 1, "expected one token in {source:?}");
+    tokens.remove(0)
+  }
+
+  fn lex_err_at(source: &str) -> (usize, usize) {
+    let err = tokenize(source).unwrap_err();
+    (err.span.line, err.span.col)
+  }
+
+  #[test]
+  fn test_semicolon_in_string() {
+    let tokens = tokenize("[print \"a;b\"] ; a comment").unwrap();
+    assert_eq!(
+      kinds(tokens),
+      vec![
+        TokenKind::LBracket,
+        TokenKind::Symbol("print".to_string()),
+        TokenKind::StringLit("a;b".to_string()),
+        TokenKind::RBracket,
+      ]
+    );
+  }
+
+  #[test]
+  fn test_comments() {
+    let tokens = tokenize("; leading\n[x];y\n; trailing").unwrap();
+    assert_eq!(
+      kinds(tokens),
+      vec![
+        TokenKind::LBracket,
+        TokenKind::Symbol("x".to_string()),
+        TokenKind::RBracket,
+      ]
+    );
+  }
+
+  #[test]
+  fn test_escapes() {
+    assert_eq!(
+      lex_one(r#""say \"hi\"""#),
+      TokenKind::StringLit("say \"hi\"".to_string())
+    );
+    assert_eq!(
+      lex_one(r#""a\nb\tc\\d""#),
+      TokenKind::StringLit("a\nb\tc\\d".to_string())
+    );
+    assert_eq!(
+      lex_one(r#""\u{48}\u{e9}\u{1F980}""#),
+      TokenKind::StringLit("H\u{e9}\u{1F980}".to_string())
+    );
+  }
+
+  #[test]
+  fn test_multi_line_string() {
+    let tokens = tokenize("[print \"one\ntwo\"]\n[x]").unwrap();
+    assert_eq!(tokens[2].kind, TokenKind::StringLit("one\ntwo".to_string()));
+    assert_eq!((tokens[2].span.line, tokens[2].span.col), (1, 8));
+    // positions after the string carry on from its last line
+    assert_eq!((tokens[3].span.line, tokens[3].span.col), (2, 5));
+    assert_eq!((tokens[5].span.line, tokens[5].span.col), (3, 2));
+  }
+
+  #[test]
+  fn test_unterminated_string() {
+    assert_eq!(lex_err_at("[print \"hello]"), (1, 8));
+    assert_eq!(lex_err_at("[x]\n  \"abc\n\ndef"), (2, 3));
+    assert_eq!(lex_err_at("\"trailing \\"), (1, 11));
+  }
+
+  #[test]
+  fn test_bad_escapes() {
+    assert_eq!(lex_err_at(r#"[print "a\qb"]"#), (1, 10));
+    assert_eq!(lex_err_at(r#""\u41""#), (1, 2));
+    assert_eq!(lex_err_at(r#""\u{}""#), (1, 2));
+    assert_eq!(lex_err_at(r#""\u{110000}""#), (1, 2));
+    assert_eq!(lex_err_at(r#""\u{1234567}""#), (1, 2));
+  }
+
+  #[test]
+  fn test_is_incomplete() {
+    assert!(!is_incomplete(""));
+    assert!(!is_incomplete("[print 1]"));
+    assert!(is_incomplete("[let sqr [fn [x]"));
+    assert!(is_incomplete("[print \"one\ntwo"));
+    assert!(is_incomplete("[print \"ends in \\"));
+    assert!(is_incomplete("[print \"]\""));
+    // too many `]`s or a bad escape won't get better with more input
+    assert!(!is_incomplete("[print 1]]"));
+    assert!(!is_incomplete("[print \"\\q"));
+    assert!(!is_incomplete("[x] ; [ in a comment"));
+  }
+
+  #[test]
+  fn test_shebang() {
+    let tokens = tokenize("#!/usr/bin/env crotchet\n[x]").unwrap();
+    assert_eq!(
+      kinds(tokens.clone()),
+      vec![
+        TokenKind::LBracket,
+        TokenKind::Symbol("x".to_string()),
+        TokenKind::RBracket,
+      ]
+    );
+    assert_eq!(tokens[0].span.line, 2);
+    // only on the first line
+    assert_eq!(lex_one(" #!x"), TokenKind::Symbol("#!x".to_string()));
+  }
+
+  #[test]
+  fn test_span_equality() {
+    let first = tokenize("[x]\n[x]").unwrap();
+    let second = tokenize("[x]\n[x]").unwrap();
+    // the same span in different sources, or different spans, aren't equal
+    assert_ne!(first[0].span, second[0].span);
+    assert_ne!(first[1].span, first[4].span);
+    // but tokens only compare what they are
+    assert_eq!(first[1], first[4]);
+    assert_eq!(first, second);
+  }
 }
@@ -0,0 +1,18 @@
+#![warn(clippy::all, clippy::pedantic)]
+// lots of the test programs use pi = 3.14
+#![cfg_attr(test, allow(clippy::approx_constant))]
+
+// the crotchet interpreter as a library, see `Interpreter` for embedding it
+mod builtins;
+pub mod env;
+pub mod error;
+pub mod eval;
+mod interpreter;
+pub mod lexer;
+pub mod object;
+pub mod parser;
+
+pub use error::CrotchetError;
+pub use eval::{eval, Limits};
+pub use interpreter::Interpreter;
+pub use object::{NativeFn, Object};
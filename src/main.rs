@@ -1,114 +1,487 @@
-#![warn(
-  clippy::all,
-  clippy::pedantic
-)]
-
-// declaring crates
-mod env;
-mod eval;
-mod lexer;
-mod object;
-mod parser;
-
-use linefeed::{Interface, ReadResult};
-use object::Object;
-use std::cell::RefCell;
+#![warn(clippy::all, clippy::pedantic)]
+
+use crotchet::error::Frame;
+use crotchet::eval::SPECIAL_FORMS;
+use crotchet::lexer::{is_incomplete, Span};
+use crotchet::{CrotchetError, Interpreter, Limits, Object};
+use linefeed::{
+  Completer, Completion, Interface, Prompter, ReadResult, Signal, Terminal,
+};
 use std::env as e; // TODO eww alias
-use std::fs::File;
-use std::io::Read;
-use std::rc::Rc;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 const PROMPT: &str = "crotchet> ";
-const EXTENSION: &str = ".crl";
+const CONTINUE_PROMPT: &str = "     ...> ";
+// exit statuses, following the BSD sysexits.h conventions
+const EX_USAGE: i32 = 64; // bad command line
+const EX_DATAERR: i32 = 65; // the program didn't lex or parse
+const EX_NOINPUT: i32 = 66; // the script couldn't be read
+const EX_SOFTWARE: i32 = 70; // the program failed while running
+const EX_IOERR: i32 = 74; // the terminal failed under the repl
+
+const HISTORY_FILE: &str = ".crotchet_history";
+const REPL_COMMANDS: &[&str] = &[
+  ":help", ":env", ":load", ":time", ":type", ":reset", ":quit",
+];
 
 fn main() {
   let args: Vec<String> = e::args().collect();
-  let argcount = args.len();
+  let mut limits = Limits::default();
+  let mut filename = None;
+  let mut expr = None;
+  let mut script_args: Vec<String> = Vec::new();
 
-  match argcount {
-    // TODO better usage error
-    argcount if argcount > 2 => {
-      eprintln!("; crotchet usage error: too many args");
-      eprintln!("; usage: crotchet [file.crl]");
-    }
-    argcount if argcount < 2 => {
-      match repl() {
-        Ok(()) => println!("; crotchet program exited successfully"),
-        Err(error) => eprintln!("; crotchet error: {error}"),
-      };
-    }
-    _ => match args[1].as_ref() {
-      "--version" | "-v" => println!("crotchet v{}", env!("CARGO_PKG_VERSION")),
+  let mut iter = args.iter().skip(1);
+  while let Some(arg) = iter.next() {
+    match arg.as_ref() {
+      "--version" | "-v" => {
+        println!("crotchet v{}", env!("CARGO_PKG_VERSION"));
+        return;
+      }
       "--help" | "-h" => {
-        println!(
-          "crotchet v{} - A LISP dialect with less `Shift`.",
-          env!("CARGO_PKG_VERSION")
+        print_help();
+        return;
+      }
+      "--max-depth" | "--max-steps" | "--max-list-len" => {
+        let Some(value) = iter.next().and_then(|v| v.parse::<u64>().ok())
+        else {
+          eprintln!("; crotchet usage error: {arg} needs a whole number");
+          process::exit(EX_USAGE);
+        };
+        let size = usize::try_from(value).ok();
+        match arg.as_ref() {
+          "--max-depth" => limits.depth = size,
+          "--max-steps" => limits.steps = Some(value),
+          _ => limits.list_len = size,
+        }
+      }
+      "-e" => {
+        let Some(source) = iter.next() else {
+          eprintln!("; crotchet usage error: -e needs an expression");
+          process::exit(EX_USAGE);
+        };
+        expr = Some(source);
+        script_args = iter.cloned().collect();
+        break;
+      }
+      _ if arg.starts_with('-') && arg != "--" && arg != "-" => {
+        eprintln!("; crotchet usage error: unknown option {arg}");
+        eprintln!(
+          "; usage: crotchet [options] [file.crl | - | -e expr] [args...]"
         );
-        println!("Usage: ");
-        println!("  crotchet filename.crl - run script named \"filename.crl\"");
-        println!("  crotchet - no arguments to enter REPL mode");
-        println!("    * input \"exit\" to leave REPL mode");
-      }
-      _ => match run_file(&args[1]) {
-        Ok(()) => {}
-        Err(error) => eprintln!("; crotchet error: {error}"),
-      },
+        process::exit(EX_USAGE);
+      }
+      // everything after the script (or after `--`) is for the script
+      "--" => {
+        filename = iter.next();
+        script_args = iter.cloned().collect();
+        break;
+      }
+      _ => {
+        filename = Some(arg);
+        script_args = iter.cloned().collect();
+        break;
+      }
+    }
+  }
+
+  let code = match (expr, filename) {
+    (Some(expr), _) => run_program(expr, "<expr>", &script_args, limits),
+    (None, Some(filename)) => match run_file(filename, &script_args, limits) {
+      Ok(code) => code,
+      Err(error) => {
+        eprintln!("; crotchet error: {filename}: {error}");
+        EX_NOINPUT
+      }
+    },
+    (None, None) => match repl() {
+      Ok(code) => {
+        if code == 0 {
+          println!("; crotchet program exited successfully");
+        }
+        code
+      }
+      Err(error) => {
+        eprintln!("; crotchet error: {error}");
+        EX_IOERR
+      }
     },
+  };
+  process::exit(code);
+}
+
+fn print_help() {
+  println!(
+    "crotchet v{} - A LISP dialect with less `Shift`.",
+    env!("CARGO_PKG_VERSION")
+  );
+  println!("Usage: ");
+  println!("  crotchet filename.crl - run script named \"filename.crl\"");
+  println!("  crotchet filename.crl args... - run a script, passing it args");
+  println!("    * the script sees them as *args*, and its path as *script*");
+  println!("  crotchet - args... - run a script read from stdin");
+  println!("  crotchet -e expr args... - run the program given as expr");
+  println!("  crotchet - no arguments to enter REPL mode");
+  println!("    * input \"exit\" to leave REPL mode");
+  println!("    * input \":help\" to list the REPL commands");
+  println!("Options: ");
+  println!("  --max-depth N - stop after N nested function calls (10000)");
+  println!("  --max-steps N - stop after evaluating N expressions");
+  println!("  --max-list-len N - stop when a list grows past N elements");
+  println!("  -- - end of options, the next arg is the script");
+  println!("Exit status: ");
+  println!("  0 on success, or the code given to [exit code]");
+  println!("  {EX_USAGE} for bad command line args");
+  println!("  {EX_DATAERR} if the program has a syntax error");
+  println!("  {EX_NOINPUT} if the script can't be read");
+  println!("  {EX_SOFTWARE} if the program fails while running");
+}
+
+// runs a script file, or stdin when the filename is `-`, returning the exit
+// status of the program
+fn run_file(
+  filename: &str,
+  script_args: &[String],
+  limits: Limits,
+) -> Result<i32, Box<dyn std::error::Error>> {
+  let mut program = String::new();
+  if filename == "-" {
+    io::stdin().read_to_string(&mut program)?;
+    Ok(run_program(&program, "<stdin>", script_args, limits))
+  } else {
+    let mut file = File::open(filename)?;
+    file.read_to_string(&mut program)?; // file contents stored in "program"
+    Ok(run_program(&program, filename, script_args, limits))
   }
 }
 
-fn run_file(filename: &str) -> Result<(), Box<dyn std::error::Error>> {
-  if !filename.ends_with(EXTENSION) {
-    return Err(format!("File must have extension {EXTENSION}").into());
+// `name` is what errors and the program's `*script*` call the source.
+// returns the exit status
+fn run_program(
+  program: &str,
+  name: &str,
+  script_args: &[String],
+  limits: Limits,
+) -> i32 {
+  let mut interp = Interpreter::with_limits(limits);
+  interp.set_global("*script*", name);
+  interp.set_global("*args*", script_args.to_vec());
+
+  match interp.eval_str(program) {
+    Ok(_) => 0,
+    Err(error) => {
+      if !matches!(error.root(), CrotchetError::Exit(_)) {
+        report(&error, name, program);
+      }
+      exit_code(&error)
+    }
   }
+}
 
-  let mut file = File::open(filename)?;
-  let mut program = String::new();
-  file.read_to_string(&mut program)?; // file contents stored in "program"
+fn exit_code(error: &CrotchetError) -> i32 {
+  match error.root() {
+    CrotchetError::Exit(code) => *code,
+    CrotchetError::Lex(_) | CrotchetError::Parse(_) => EX_DATAERR,
+    _ => EX_SOFTWARE,
+  }
+}
 
-  // eval the file contents
-  let mut env = Rc::new(RefCell::new(env::Env::new()));
+// prints an error along with the offending source line and the calls it
+// happened in, e.g.
+//   ; crotchet error: Unbound symbol: foo
+//   ;  --> tour.crl:3:10
+//   ;   |
+//   ; 3 |   [print foo]
+//   ;   |          ^^^
+//   ; in [show 2] at tour.crl:5:3
+fn report(error: &CrotchetError, filename: &str, source: &str) {
+  eprintln!("; crotchet error: {}", error.root());
 
-  eval::eval(program.as_ref(), &mut env)?;
+  if let Some(span) = error.span() {
+    eprintln!(";  --> {filename}:{span}");
+    report_source_line(span, source);
+  }
+  report_trace(error.trace(), filename);
+}
+
+fn report_source_line(span: Span, source: &str) {
+  let Some(line) = source.lines().nth(span.line - 1) else {
+    return;
+  };
+  let gutter = " ".repeat(span.line.to_string().len());
+  // keep tabs in the padding so the caret lines up with the source
+  let padding: String = line
+    .chars()
+    .take(span.col - 1)
+    .map(|c| if c == '\t' { '\t' } else { ' ' })
+    .collect();
+
+  eprintln!("; {gutter} |");
+  eprintln!("; {} | {line}", span.line);
+  eprintln!("; {gutter} | {padding}{}", "^".repeat(span.len.max(1)));
+}
+
+// deep recursion can leave thousands of frames, so only the innermost and
+// outermost few are shown
+fn report_trace(frames: &[Frame], filename: &str) {
+  const SHOWN: usize = 10;
 
-  Ok(())
+  for (i, frame) in frames.iter().enumerate() {
+    if frames.len() > SHOWN * 2 && i == SHOWN {
+      eprintln!("; ... {} more calls", frames.len() - SHOWN * 2);
+    }
+    if frames.len() > SHOWN * 2 && i >= SHOWN && i < frames.len() - SHOWN {
+      continue;
+    }
+
+    let verb = if i == 0 { "in" } else { "called from" };
+    match frame.span {
+      Some(span) => eprintln!("; {verb} {frame} at {filename}:{span}"),
+      None => eprintln!("; {verb} {frame}"),
+    }
+  }
 }
 
-fn repl() -> Result<(), Box<dyn std::error::Error>> {
+// returns the exit status, which is only non-zero if the user calls `exit`
+fn repl() -> Result<i32, Box<dyn std::error::Error>> {
   println!(
-    "; Welcome to crotchet v{}, type `exit` to exit",
+    "; Welcome to crotchet v{}, type `:help` for help or `exit` to exit",
     env!("CARGO_PKG_VERSION")
   );
-  let reader = Interface::new(PROMPT).unwrap();
-  let mut env = Rc::new(RefCell::new(env::Env::new()));
+  let reader = Interface::new(PROMPT)?;
+  let mut interp = Interpreter::new();
 
-  reader.set_prompt(PROMPT.as_ref()).unwrap();
+  // there's no history on the first run, that's fine
+  let history = history_path();
+  if let Some(path) = &history {
+    let _ = reader.load_history(path);
+  }
 
-  // TODO split this out into its own function?
-  while let ReadResult::Input(input) = reader.read_line().unwrap() {
-    if input.eq("exit") {
+  let names = Arc::new(Mutex::new(Vec::new()));
+  refresh_names(&names, &interp);
+  reader.set_completer(Arc::new(NameCompleter {
+    names: names.clone(),
+  }));
+
+  reader.set_prompt(PROMPT)?;
+  // ctrl-c throws away a half typed form instead of killing the repl
+  reader.set_report_signal(Signal::Interrupt, true);
+
+  // lines are buffered until their brackets balance
+  let mut input = String::new();
+  let mut code = 0;
+
+  loop {
+    let line = match reader.read_line()? {
+      ReadResult::Input(line) => line,
+      ReadResult::Signal(Signal::Interrupt) => {
+        reader.cancel_read_line()?;
+        input.clear();
+        reader.set_prompt(PROMPT)?;
+        continue;
+      }
+      ReadResult::Signal(_) => continue,
+      ReadResult::Eof => break,
+    };
+    if !line.trim().is_empty() {
+      reader.add_history_unique(line.clone());
+    }
+
+    if input.is_empty() && line.trim() == "exit" {
       break;
     }
 
-    let val = eval::eval(input.as_ref(), &mut env)?;
-    match val {
-      Object::Void => {}
-      Object::Number(n) => println!("; {n}"),
-      Object::Bool(b) => println!("; {b}"),
-      Object::Symbol(s) => println!("; {s}"),
-      Object::Lambda(params, body) => {
-        println!("; fn[");
-        for param in params {
-          println!("{param} ");
+    input.push_str(&line);
+    input.push('\n');
+    if is_incomplete(&input) {
+      reader.set_prompt(CONTINUE_PROMPT)?;
+      continue;
+    }
+    reader.set_prompt(PROMPT)?;
+
+    // a mistake shouldn't cost the user everything they've defined so far
+    let source = std::mem::take(&mut input);
+    let quit = if source.trim_start().starts_with(':') {
+      run_command(&source, &mut interp)
+    } else {
+      match interp.eval_str(&source) {
+        Ok(val) => {
+          print_value(&val);
+          None
         }
-        println!("]");
-        for expr in body {
-          println!(" {expr}");
+        Err(error) => report_repl(&error, "<repl>", &source),
+      }
+    };
+    if let Some(status) = quit {
+      code = status;
+      break;
+    }
+    refresh_names(&names, &interp);
+  }
+
+  if let Some(path) = &history {
+    if let Err(error) = reader.save_history(path) {
+      eprintln!("; couldn't save history to {}: {error}", path.display());
+    }
+  }
+  Ok(code)
+}
+
+fn history_path() -> Option<PathBuf> {
+  e::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+// tab completes special forms, repl commands and every name bound in the
+// global env. linefeed needs a completer it can share between threads, which
+// the `Env` can't be, so it gets a copy of the names after each entry
+struct NameCompleter {
+  names: Arc<Mutex<Vec<String>>>,
+}
+
+impl<Term: Terminal> Completer<Term> for NameCompleter {
+  fn complete(
+    &self,
+    word: &str,
+    _prompter: &Prompter<Term>,
+    _start: usize,
+    _end: usize,
+  ) -> Option<Vec<Completion>> {
+    let names = self.names.lock().ok()?;
+    let matches = names
+      .iter()
+      .filter(|name| name.starts_with(word))
+      .map(|name| Completion::simple(name.clone()))
+      .collect();
+    Some(matches)
+  }
+
+  // a name can start right after a `[`, not just after a space
+  fn word_start(
+    &self,
+    line: &str,
+    end: usize,
+    _prompter: &Prompter<Term>,
+  ) -> usize {
+    line[..end]
+      .char_indices()
+      .rev()
+      .find(|&(_, c)| c.is_whitespace() || "[]\"".contains(c))
+      .map_or(0, |(i, c)| i + c.len_utf8())
+  }
+}
+
+fn refresh_names(names: &Mutex<Vec<String>>, interp: &Interpreter) {
+  let bound = interp.env().borrow().bindings();
+  let mut all: Vec<String> = SPECIAL_FORMS
+    .iter()
+    .chain(REPL_COMMANDS)
+    .map(ToString::to_string)
+    .chain(bound.into_iter().map(|(name, _)| name))
+    .collect();
+  all.sort();
+  all.dedup();
+  if let Ok(mut names) = names.lock() {
+    *names = all;
+  }
+}
+
+// reports an error at the repl, unless it's the program calling `exit`, in
+// which case it's the status to quit with
+fn report_repl(error: &CrotchetError, name: &str, source: &str) -> Option<i32> {
+  if let CrotchetError::Exit(code) = error.root() {
+    return Some(*code);
+  }
+  report(error, name, source);
+  None
+}
+
+// handles a `:command` typed at the repl, returning a status if it's time to
+// quit
+fn run_command(source: &str, interp: &mut Interpreter) -> Option<i32> {
+  let trimmed = source.trim();
+  let (command, arg) = trimmed
+    .split_once(char::is_whitespace)
+    .unwrap_or((trimmed, ""));
+  let arg = arg.trim();
+  // blank out the command so spans in the expression still line up with
+  // `source` when an error is reported
+  let expr = source.replacen(command, &" ".repeat(command.len()), 1);
+
+  match command {
+    ":help" => print_repl_help(),
+    ":quit" => return Some(0),
+    ":reset" => {
+      *interp = Interpreter::new();
+      println!("; environment reset");
+    }
+    ":env" => {
+      for (name, val) in interp.env().borrow().bindings() {
+        match val {
+          Object::Lambda(_) | Object::NativeFn(_) => println!("; {name}: fn"),
+          _ => println!("; {name}: {} = {val}", val.type_name()),
         }
       }
-      _ => println!("; {val}"),
     }
+    ":load" if !arg.is_empty() => match fs::read_to_string(arg) {
+      Ok(program) => match interp.eval_str(&program) {
+        Ok(_) => println!("; loaded {arg}"),
+        Err(error) => return report_repl(&error, arg, &program),
+      },
+      Err(error) => eprintln!("; crotchet error: io: {arg}: {error}"),
+    },
+    ":time" if !arg.is_empty() => {
+      let start = Instant::now();
+      let result = interp.eval_str(&expr);
+      let elapsed = start.elapsed();
+      match result {
+        Ok(val) => print_value(&val),
+        Err(error) => return report_repl(&error, "<repl>", source),
+      }
+      println!("; took {elapsed:?}");
+    }
+    ":type" if !arg.is_empty() => match interp.eval_str(&expr) {
+      Ok(val) => println!("; {}", val.type_name()),
+      Err(error) => return report_repl(&error, "<repl>", source),
+    },
+    ":load" | ":time" | ":type" => {
+      eprintln!("; crotchet usage error: {command} needs an argument");
+    }
+    _ => eprintln!("; unknown command {command}, try :help"),
   }
+  None
+}
 
-  Ok(())
+fn print_repl_help() {
+  println!("; :help        show this message");
+  println!("; :env         list global bindings and their types");
+  println!("; :load FILE   evaluate a script into this session");
+  println!("; :time EXPR   evaluate EXPR and report how long it took");
+  println!("; :type EXPR   evaluate EXPR and show the type of its value");
+  println!("; :reset       start over with a fresh environment");
+  println!("; :quit        leave the repl (or type `exit`)");
+}
+
+fn print_value(val: &Object) {
+  match val {
+    Object::Void => {}
+    Object::Number(n) => println!("; {n}"),
+    Object::Bool(b) => println!("; {b}"),
+    Object::Symbol(s, _) => println!("; {s}"),
+    Object::Lambda(lambda) => {
+      println!("; fn[");
+      for param in lambda.param_list() {
+        println!("{param} ");
+      }
+      println!("]");
+      for expr in lambda.body.iter() {
+        println!(" {expr}");
+      }
+    }
+    _ => println!("; {val}"),
+  }
 }
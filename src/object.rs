@@ -1,37 +1,155 @@
+use crate::env::Env;
+use crate::error::CrotchetError;
+use crate::lexer::Span;
+use std::cell::RefCell;
 use std::fmt;
 use std::rc::Rc;
 
-#[derive(Debug, Clone, PartialEq)]
+// a `fn` value: parameters, body, and the environment it was defined in.
+// `[fn [x [y 1] & more] ...]` has params `x`, optional `y` which is 1 if it's
+// not passed, and `more` for a list of any args after that
+#[derive(Clone)]
+pub struct Lambda {
+  pub params: Vec<String>,
+  pub optional: Vec<(String, Object)>, // the defaults are unevaluated
+  pub rest: Option<String>,
+  pub body: Rc<Vec<Object>>,
+  pub env: Rc<RefCell<Env>>,
+}
+
+impl Lambda {
+  // the parameter list as it was written, e.g. ["x", "[y 1]", "&", "more"]
+  #[must_use]
+  pub fn param_list(&self) -> Vec<String> {
+    let mut list = self.params.clone();
+    for (name, default) in &self.optional {
+      list.push(format!("[{name} {default}]"));
+    }
+    if let Some(rest) = &self.rest {
+      list.push("&".to_string());
+      list.push(rest.clone());
+    }
+    list
+  }
+}
+
+// the captured env usually contains the lambda itself (e.g. recursive
+// functions), so comparing or debug-printing it would never terminate
+impl PartialEq for Lambda {
+  fn eq(&self, other: &Self) -> bool {
+    self.params == other.params
+      && self.optional == other.optional
+      && self.rest == other.rest
+      && self.body == other.body
+      && Rc::ptr_eq(&self.env, &other.env)
+  }
+}
+
+impl fmt::Debug for Lambda {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Lambda")
+      .field("params", &self.params)
+      .field("optional", &self.optional)
+      .field("rest", &self.rest)
+      .field("body", &self.body)
+      .finish_non_exhaustive()
+  }
+}
+
+// what a native function does with its (already evaluated) args
+pub type NativeFnBody = dyn Fn(&[Object]) -> Result<Object, CrotchetError>;
+
+// a function implemented in Rust. `arity` is the exact number of args it
+// takes, or `None` if it checks them itself
+#[derive(Clone)]
+pub struct NativeFn {
+  pub name: String,
+  pub arity: Option<usize>,
+  pub func: Rc<NativeFnBody>,
+}
+
+impl NativeFn {
+  pub fn new(
+    name: &str,
+    arity: Option<usize>,
+    func: impl Fn(&[Object]) -> Result<Object, CrotchetError> + 'static,
+  ) -> Self {
+    NativeFn {
+      name: name.to_string(),
+      arity,
+      func: Rc::new(func),
+    }
+  }
+}
+
+impl PartialEq for NativeFn {
+  fn eq(&self, other: &Self) -> bool {
+    self.name == other.name && Rc::ptr_eq(&self.func, &other.func)
+  }
+}
+
+impl fmt::Debug for NativeFn {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("NativeFn")
+      .field("name", &self.name)
+      .field("arity", &self.arity)
+      .finish_non_exhaustive()
+  }
+}
+
+#[derive(Debug, Clone)]
 pub enum Object {
   Void,
   Number(f64),
   Bool(bool),
-  Symbol(String),
-  Lambda(Vec<String>, Vec<Object>),
-  List(Rc<Vec<Object>>),
+  Symbol(String, Span),
+  Lambda(Lambda),
+  NativeFn(NativeFn),
+  List(Rc<Vec<Object>>, Span),
   ListData(Vec<Object>),
   String(String),
 }
 
+// spans are just bookkeeping, so the same code parsed from different places
+// is equal
+impl PartialEq for Object {
+  #[allow(clippy::float_cmp)] // numbers compare exactly, like the derive did
+  fn eq(&self, other: &Self) -> bool {
+    match (self, other) {
+      (Object::Void, Object::Void) => true,
+      (Object::Number(a), Object::Number(b)) => a == b,
+      (Object::Bool(a), Object::Bool(b)) => a == b,
+      (Object::Symbol(a, _), Object::Symbol(b, _))
+      | (Object::String(a), Object::String(b)) => a == b,
+      (Object::Lambda(a), Object::Lambda(b)) => a == b,
+      (Object::NativeFn(a), Object::NativeFn(b)) => a == b,
+      (Object::List(a, _), Object::List(b, _)) => a == b,
+      (Object::ListData(a), Object::ListData(b)) => a == b,
+      _ => false,
+    }
+  }
+}
+
 impl fmt::Display for Object {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
       Object::Void => write!(f, "Void"),
       Object::Number(n) => write!(f, "{n}"),
       Object::Bool(b) => write!(f, "{b}"),
-      Object::Symbol(s) | Object::String(s) => write!(f, "{s}"),
-      Object::Lambda(params, body) => {
+      Object::Symbol(s, _) | Object::String(s) => write!(f, "{s}"),
+      Object::Lambda(lambda) => {
         writeln!(f, "fn[")?;
-        for param in params {
+        for param in lambda.param_list() {
           writeln!(f, "{param} ")?;
         }
         writeln!(f, "]")?;
-        for expr in body {
+        for expr in lambda.body.iter() {
           writeln!(f, " {expr}")?;
         }
         Ok(())
       }
-      Object::List(list) => {
+      Object::NativeFn(native) => write!(f, "<builtin {}>", native.name),
+      Object::List(list, _) => {
         write!(f, "[")?;
         for (i, obj) in (*list).iter().enumerate() {
           if i > 0 {
@@ -54,3 +172,102 @@ impl fmt::Display for Object {
     }
   }
 }
+
+impl Object {
+  // name of the type as it should appear in messages
+  #[must_use]
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Object::Void => "void",
+      Object::Number(_) => "number",
+      Object::Bool(_) => "bool",
+      Object::Symbol(..) => "symbol",
+      Object::Lambda(_) | Object::NativeFn(_) => "fn",
+      Object::List(..) | Object::ListData(_) => "list",
+      Object::String(_) => "string",
+    }
+  }
+}
+
+// conversions for passing values between Rust and crotchet
+
+impl From<f64> for Object {
+  fn from(n: f64) -> Self {
+    Object::Number(n)
+  }
+}
+
+impl From<bool> for Object {
+  fn from(b: bool) -> Self {
+    Object::Bool(b)
+  }
+}
+
+impl From<String> for Object {
+  fn from(s: String) -> Self {
+    Object::String(s)
+  }
+}
+
+impl From<&str> for Object {
+  fn from(s: &str) -> Self {
+    Object::String(s.to_string())
+  }
+}
+
+impl<T: Into<Object>> From<Vec<T>> for Object {
+  fn from(items: Vec<T>) -> Self {
+    Object::ListData(items.into_iter().map(Into::into).collect())
+  }
+}
+
+fn expected(what: &str, obj: &Object) -> CrotchetError {
+  CrotchetError::type_error(format!(
+    "Expected a {what}, found a {}",
+    obj.type_name()
+  ))
+}
+
+impl TryFrom<Object> for f64 {
+  type Error = CrotchetError;
+
+  fn try_from(obj: Object) -> Result<Self, Self::Error> {
+    match obj {
+      Object::Number(n) => Ok(n),
+      _ => Err(expected("number", &obj)),
+    }
+  }
+}
+
+impl TryFrom<Object> for bool {
+  type Error = CrotchetError;
+
+  fn try_from(obj: Object) -> Result<Self, Self::Error> {
+    match obj {
+      Object::Bool(b) => Ok(b),
+      _ => Err(expected("bool", &obj)),
+    }
+  }
+}
+
+impl TryFrom<Object> for String {
+  type Error = CrotchetError;
+
+  fn try_from(obj: Object) -> Result<Self, Self::Error> {
+    match obj {
+      Object::String(s) => Ok(s),
+      _ => Err(expected("string", &obj)),
+    }
+  }
+}
+
+impl<T: TryFrom<Object, Error = CrotchetError>> TryFrom<Object> for Vec<T> {
+  type Error = CrotchetError;
+
+  fn try_from(obj: Object) -> Result<Self, Self::Error> {
+    match obj {
+      Object::ListData(items) => items.into_iter().map(T::try_from).collect(),
+      _ => Err(expected("list", &obj)),
+    }
+  }
+}
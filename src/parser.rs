@@ -1,4 +1,5 @@
-use crate::lexer::{tokenize, Token};
+use crate::error::CrotchetError;
+use crate::lexer::{tokenize, Span, Token, TokenKind};
 use crate::object::Object;
 use std::error::Error;
 use std::fmt;
@@ -6,7 +7,8 @@ use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct ParseError {
-  err: String,
+  pub err: String,
+  pub span: Span,
 }
 
 impl fmt::Display for ParseError {
@@ -17,56 +19,109 @@ impl fmt::Display for ParseError {
 
 impl Error for ParseError {}
 
-pub fn parse(program: &str) -> Result<Object, ParseError> {
-  let token_result = tokenize(program);
-  if token_result.is_err() {
-    return Err(ParseError {
-      err: format!("{}", token_result.err().unwrap()),
-    });
+// parsing, evaluating and dropping a list all recurse into its sublists, so
+// past this the Rust stack could run out on snippets like `[[[[...]]]]`
+const MAX_NESTING: usize = 1000;
+
+/// Parses crotchet source into its top-level forms, in order.
+///
+/// # Errors
+///
+/// Returns [`CrotchetError::Lex`] or [`CrotchetError::Parse`] for malformed
+/// source.
+pub fn parse(program: &str) -> Result<Vec<Object>, CrotchetError> {
+  let tokens = tokenize(program)?;
+  let mut tokens = tokens.into_iter().rev().collect::<Vec<_>>();
+
+  let mut forms = Vec::new();
+  while let Some(token) = tokens.pop() {
+    match token.kind {
+      TokenKind::LBracket => {
+        tokens.push(token);
+        forms.push(parse_list(&mut tokens, 1)?);
+      }
+      TokenKind::RBracket => {
+        return Err(
+          ParseError {
+            err: "Unexpected `]` with no `[` to close".to_string(),
+            span: token.span,
+          }
+          .into(),
+        )
+      }
+      _ => forms.push(parse_atom(token)),
+    }
+  }
+  Ok(forms)
+}
+
+fn parse_atom(token: Token) -> Object {
+  match token.kind {
+    TokenKind::Number(n) => Object::Number(n),
+    TokenKind::Symbol(s) => match s.as_ref() {
+      "true" => Object::Bool(true),
+      "false" => Object::Bool(false),
+      _ => Object::Symbol(s, token.span),
+    },
+    TokenKind::StringLit(s) => Object::String(s),
+    TokenKind::LBracket | TokenKind::RBracket => {
+      unreachable!("brackets are handled by `parse_list`")
+    }
   }
-  let mut tokens = token_result.unwrap().into_iter().rev().collect::<Vec<_>>();
-  let parsed_list = parse_list(&mut tokens)?;
-  Ok(parsed_list)
 }
 
-fn parse_list(tokens: &mut Vec<Token>) -> Result<Object, ParseError> {
+// `depth` is how many lists deep this one is, counting itself
+fn parse_list(
+  tokens: &mut Vec<Token>,
+  depth: usize,
+) -> Result<Object, ParseError> {
   let token = tokens.pop();
 
-  if token != Some(Token::LBracket) {
+  let open = match token {
+    Some(Token {
+      kind: TokenKind::LBracket,
+      span,
+    }) => span,
+    Some(Token { kind, span }) => {
+      return Err(ParseError {
+        err: format!("Expected `[`, found `{kind}`"),
+        span,
+      })
+    }
+    None => {
+      return Err(ParseError {
+        err: "Expected `[`, found end of input".to_string(),
+        span: Span::default(),
+      })
+    }
+  };
+  if depth > MAX_NESTING {
     return Err(ParseError {
-      err: format!("Expected `[`, found {token:?}"),
+      err: format!("Brackets nested more than {MAX_NESTING} deep"),
+      span: open,
     });
   }
 
   let mut list: Vec<Object> = Vec::new();
-  while !tokens.is_empty() {
-    let token = tokens.pop();
-    if token.is_none() {
-      return Err(ParseError {
-        err: "Did not find enough tokens".to_string(),
-      });
-    }
-
-    match token.unwrap() {
-      Token::Number(n) => list.push(Object::Number(n)),
-      Token::Symbol(s) => match s.as_ref() {
-        "true" => list.push(Object::Bool(true)),
-        "false" => list.push(Object::Bool(false)),
-        _ => list.push(Object::Symbol(s)),
-      },
-      Token::StringLit(s) => list.push(Object::String(s)),
-      Token::LBracket => {
-        tokens.push(Token::LBracket);
-        let sub_list = parse_list(tokens)?; // recursive call
+  while let Some(token) = tokens.pop() {
+    match token.kind {
+      TokenKind::LBracket => {
+        tokens.push(token);
+        let sub_list = parse_list(tokens, depth + 1)?; // recursive call
         list.push(sub_list);
       }
-      Token::RBracket => {
-        return Ok(Object::List(Rc::new(list)));
+      TokenKind::RBracket => {
+        return Ok(Object::List(Rc::new(list), open));
       }
+      _ => list.push(parse_atom(token)),
     }
   }
 
-  Ok(Object::List(list.into()))
+  // ran out of tokens before the `]`, so point back at the `[`
+  Err(ParseError {
+    err: "Unclosed `[`, expected a matching `]`".to_string(),
+    span: open,
+  })
 }
 
 #[cfg(test)]
@@ -74,6 +129,14 @@ mod tests {
 
   use super::*;
 
+  fn list_of(items: Vec<Object>) -> Object {
+    Object::List(items.into(), Span::default())
+  }
+
+  fn sym(s: &str) -> Object {
+    Object::Symbol(s.to_string(), Span::default())
+  }
+
   #[test]
   fn test_add() {
     let program = "[+ 2 1]";
@@ -82,14 +145,11 @@ mod tests {
 
     assert_eq!(
       list,
-      Object::List(
-        vec![
-          Object::Symbol("+".to_string()),
-          Object::Number(2.0),
-          Object::Number(1.0),
-        ]
-        .into()
-      )
+      vec![list_of(vec![
+        sym("+"),
+        Object::Number(2.0),
+        Object::Number(1.0)
+      ])]
     );
   }
 
@@ -104,42 +164,109 @@ mod tests {
 
     assert_eq!(
       list,
-      Object::List(
-        vec![
-          Object::List(
-            vec![
-              Object::Symbol("let".to_string()),
-              Object::Symbol("r".to_string()),
-              Object::Number(10.0),
-            ]
-            .into()
-          ),
-          Object::List(
-            vec![
-              Object::Symbol("let".to_string()),
-              Object::Symbol("pi".to_string()),
-              Object::Number(3.14),
-            ]
-            .into()
-          ),
-          Object::List(
-            vec![
-              Object::Symbol("*".to_string()),
-              Object::Symbol("pi".to_string()),
-              Object::List(
-                vec![
-                  Object::Symbol("*".to_string()),
-                  Object::Symbol("r".to_string()),
-                  Object::Symbol("r".to_string()),
-                ]
-                .into()
-              ),
-            ]
-            .into()
-          ),
-        ]
-        .into()
-      )
+      vec![list_of(vec![
+        list_of(vec![sym("let"), sym("r"), Object::Number(10.0)]),
+        list_of(vec![sym("let"), sym("pi"), Object::Number(3.14)]),
+        list_of(vec![
+          sym("*"),
+          sym("pi"),
+          list_of(vec![sym("*"), sym("r"), sym("r")])
+        ])
+      ])]
+    );
+  }
+
+  #[test]
+  fn test_multiple_forms() {
+    let program = "
+      [let r 10]
+      r
+      [print r]
+    ";
+    let forms = parse(program).unwrap();
+
+    assert_eq!(
+      forms,
+      vec![
+        list_of(vec![sym("let"), sym("r"), Object::Number(10.0)]),
+        sym("r"),
+        list_of(vec![sym("print"), sym("r")])
+      ]
     );
   }
+
+  #[test]
+  fn test_trailing_bracket() {
+    let err = parse("[print 1]]").unwrap_err();
+    assert!(matches!(err, CrotchetError::Parse(_)));
+    assert_eq!(err.span().unwrap().col, 10);
+  }
+
+  fn parse_err_at(program: &str) -> (usize, usize) {
+    match parse(program) {
+      Err(CrotchetError::Parse(e)) => (e.span.line, e.span.col),
+      other => panic!("expected a parse error for {program:?}, got {other:?}"),
+    }
+  }
+
+  #[test]
+  fn test_empty_program() {
+    assert_eq!(parse("").unwrap(), vec![]);
+    assert_eq!(parse("  ; just a comment").unwrap(), vec![]);
+  }
+
+  #[test]
+  fn test_unclosed_bracket() {
+    assert_eq!(parse_err_at("[print \"hi\""), (1, 1));
+    assert_eq!(parse_err_at("["), (1, 1));
+    assert_eq!(parse_err_at("[print 1] [print 2"), (1, 11));
+  }
+
+  #[test]
+  fn test_unclosed_outer_bracket() {
+    // the inner list is fine, it's the outer one that never closes
+    assert_eq!(parse_err_at("[let x [+ 1 2]"), (1, 1));
+  }
+
+  #[test]
+  fn test_unclosed_inner_bracket() {
+    assert_eq!(parse_err_at("[let x [+ 1 2"), (1, 8));
+  }
+
+  #[test]
+  fn test_unclosed_bracket_over_lines() {
+    let program = "[let x 1]
+[while true
+  [print x]";
+    assert_eq!(parse_err_at(program), (2, 1));
+  }
+
+  #[test]
+  fn test_stray_closing_bracket() {
+    assert_eq!(parse_err_at("]"), (1, 1));
+    assert_eq!(parse_err_at("x ]"), (1, 3));
+    assert_eq!(parse_err_at("[let x 1]\n  ]\n[print x]"), (2, 3));
+  }
+
+  #[test]
+  fn test_unclosed_message() {
+    let err = parse("[print 1").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Parser error: Unclosed `[`, expected a matching `]`"
+    );
+  }
+
+  #[test]
+  fn test_nesting_limit() {
+    let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+    assert!(parse(&nested(MAX_NESTING)).is_ok());
+
+    for depth in [MAX_NESTING + 1, 20_000] {
+      let err = parse(&nested(depth)).unwrap_err();
+      assert!(matches!(err, CrotchetError::Parse(_)), "{err:?}");
+      // points at the first `[` past the limit
+      assert_eq!(err.span().unwrap().col, MAX_NESTING + 1);
+    }
+  }
 }
@@ -0,0 +1,121 @@
+// runs the built `crotchet` binary and checks what it prints and exits with
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{self, Command, Output, Stdio};
+
+fn crotchet(args: &[&str]) -> Output {
+  Command::new(env!("CARGO_BIN_EXE_crotchet"))
+    .args(args)
+    .output()
+    .unwrap()
+}
+
+fn crotchet_stdin(args: &[&str], input: &str) -> Output {
+  let mut child = Command::new(env!("CARGO_BIN_EXE_crotchet"))
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+  child
+    .stdin
+    .take()
+    .unwrap()
+    .write_all(input.as_bytes())
+    .unwrap();
+  child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+  String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+  String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_expr() {
+  let output = crotchet(&["-e", "[print [+ 1 2]]"]);
+  assert_eq!(output.status.code(), Some(0));
+  assert_eq!(stdout(&output), "3\n");
+}
+
+#[test]
+fn test_stdin() {
+  let output = crotchet_stdin(&["-", "a"], "[print *script* *args*]");
+  assert_eq!(output.status.code(), Some(0));
+  assert_eq!(stdout(&output), "<stdin>[a]\n");
+}
+
+#[test]
+fn test_script_args() {
+  let path = env::temp_dir().join(format!("crotchet-{}.crl", process::id()));
+  fs::write(&path, "[print [len *args*] [first *args*]]").unwrap();
+  let output = crotchet(&[path.to_str().unwrap(), "one", "two"]);
+  fs::remove_file(&path).unwrap();
+
+  assert_eq!(output.status.code(), Some(0));
+  assert_eq!(stdout(&output), "2one\n");
+}
+
+#[test]
+fn test_exit() {
+  let output = crotchet(&["-e", "[print 1] [exit 3] [print 2]"]);
+  assert_eq!(output.status.code(), Some(3));
+  assert_eq!(stdout(&output), "1\n");
+  assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn test_usage_error() {
+  let output = crotchet(&["--bogus"]);
+  assert_eq!(output.status.code(), Some(64));
+  assert!(stderr(&output).contains("unknown option --bogus"));
+
+  let output = crotchet(&["--max-depth", "lots", "-e", "1"]);
+  assert_eq!(output.status.code(), Some(64));
+}
+
+#[test]
+fn test_parse_error() {
+  let output = crotchet(&["-e", "[print 1"]);
+  assert_eq!(output.status.code(), Some(65));
+  assert!(stderr(&output).contains("crotchet error"));
+}
+
+#[test]
+fn test_deep_nesting() {
+  let program = "[".repeat(20_000) + &"]".repeat(20_000);
+  let output = crotchet_stdin(
+    &[
+      "--max-depth",
+      "100",
+      "--max-steps",
+      "1000",
+      "--max-list-len",
+      "10",
+      "-",
+    ],
+    &program,
+  );
+  assert_eq!(output.status.code(), Some(65));
+  assert!(stderr(&output).contains("nested more than"));
+}
+
+#[test]
+fn test_missing_script() {
+  let output = crotchet(&["no/such/script.crl"]);
+  assert_eq!(output.status.code(), Some(66));
+  assert!(stderr(&output).contains("no/such/script.crl"));
+}
+
+#[test]
+fn test_runtime_error() {
+  let output = crotchet(&["-e", "[print 1] [+ 1 true]"]);
+  assert_eq!(output.status.code(), Some(70));
+  assert_eq!(stdout(&output), "1\n");
+  assert!(stderr(&output).contains("<expr>:1:11"));
+}